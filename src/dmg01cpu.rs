@@ -1,14 +1,19 @@
 mod apu;
 mod cartridge;
 mod joypad;
+mod palette;
 mod ppu;
+mod sgb;
 mod timer;
 
 use super::Log;
 use apu::APU;
 use cartridge::Cartridge;
 use joypad::Joypad;
+pub use ppu::Renderer;
 use ppu::PPU;
+pub use ppu::{write_rgb565, write_rgba8888};
+use sgb::Sgb;
 use timer::Timer;
 
 const OPECODE_CYCLES: [u8; 256] = [
@@ -61,6 +66,14 @@ pub struct Dmg01Cpu {
     interrupt_flag: u8,
     /* Halt */
     halt: u8, // cpu halt
+    /* CGB */
+    is_cgb: bool,
+    key1: u8, // 0x80:double speed, 0x01:prepare speed switch
+    svbk: u8, // wram bank
+    hdma_source: u16,
+    hdma_destination: u16, // vram offset
+    hdma5: u8,             // 0x80:inactive, 0x7f:remaining blocks of 16 bytes - 1
+    dma_stall: u16,        // cpu cycles
     /* Registers */
     a: u8,   // accumulator
     f: u8,   // flag
@@ -73,7 +86,7 @@ pub struct Dmg01Cpu {
     sp: u16, // stack pointer
     pc: u16, // program counter
     /* Memory */
-    ram: [u8; 0x2000], // C000 - DFFF
+    ram: [u8; 0x8000], // C000 - DFFF, 8 banks of 4KB on cgb
     hram: [u8; 0x7f],  // FF80 - FFFE
     /* Peripheral */
     timer: Timer,
@@ -81,17 +94,25 @@ pub struct Dmg01Cpu {
     pub apu: APU,
     pub joypad: Joypad,
     pub cartridge: Cartridge,
+    sgb: Option<Sgb>,
 }
 
 impl Dmg01Cpu {
     pub fn new(log_mode: u8, romfile: String) -> Self {
-        let dmg01cpu = Dmg01Cpu {
+        let mut dmg01cpu = Dmg01Cpu {
             log_mode,
             cycle: 0,
             ime: 0x00,
             interrupt_flag: 0x00,
             interrupt_enable: 0x00,
             halt: 0,
+            is_cgb: false,
+            key1: 0x00,
+            svbk: 0x00,
+            hdma_source: 0x0000,
+            hdma_destination: 0x0000,
+            hdma5: 0xff,
+            dma_stall: 0,
             a: 0x00,
             f: 0x00,
             b: 0x00,
@@ -102,18 +123,74 @@ impl Dmg01Cpu {
             l: 0x00,
             sp: 0x0000,
             pc: 0x0100, // entry point
-            ram: [0; 0x2000],
+            ram: [0; 0x8000],
             hram: [0; 0x7f],
             timer: Timer::new(),
             apu: APU::new(log_mode),
             ppu: PPU::new(log_mode),
             joypad: Joypad::new(),
             cartridge: Cartridge::new(log_mode, romfile),
+            sgb: None,
         };
 
+        if dmg01cpu.cartridge.is_cgb() {
+            dmg01cpu.is_cgb = true;
+            dmg01cpu.a = 0x11; // games check a for the cgb
+            dmg01cpu.ppu.set_cgb_mode(true);
+        } else if dmg01cpu.cartridge.is_sgb() {
+            dmg01cpu.sgb = Some(Sgb::new(log_mode));
+            dmg01cpu.joypad.set_sgb_mode(true);
+        }
+
         dmg01cpu
     }
 
+    /// classic, gray, green, cgb (title colorization) or a palette file, dmg only
+    pub fn set_palette(&mut self, name: &str) {
+        let palettes: palette::DmgPalettes = match name {
+            "classic" => palette::CLASSIC,
+            "gray" => palette::POCKET_GRAY,
+            "green" => palette::DMG_GREEN,
+            "cgb" => palette::get_cgb_palettes(&self.cartridge.rom),
+            _ => match palette::load_file(name) {
+                Ok(result) => result,
+                Err(error) => {
+                    // a mistyped preset name or a broken file
+                    println!("{}", error);
+                    println!("palettes:classic|gray|green|cgb|<palette file>, classic is used");
+                    palette::CLASSIC
+                }
+            },
+        };
+        self.ppu.set_dmg_palettes(palettes);
+    }
+
+    /// 160x144, 256x224 with the sgb border
+    pub fn get_screen_size(&self) -> (u32, u32) {
+        match self.sgb {
+            Some(_) => (sgb::SGB_WIDTH as u32, sgb::SGB_HEIGHT as u32),
+            None => (160, 144),
+        }
+    }
+
+    /// rgb555, the sgb frame includes the border
+    pub fn get_frame_buffer(&self) -> &[u16] {
+        match &self.sgb {
+            Some(sgb) => sgb.get_frame_buffer(),
+            None => self.ppu.get_frame_buffer(),
+        }
+    }
+
+    pub fn write_rgba8888(&self, buffer: &mut [u8], pitch: usize) {
+        let (width, _) = self.get_screen_size();
+        write_rgba8888(self.get_frame_buffer(), width as usize, buffer, pitch);
+    }
+
+    pub fn write_rgb565(&self, buffer: &mut [u8], pitch: usize) {
+        let (width, _) = self.get_screen_size();
+        write_rgb565(self.get_frame_buffer(), width as usize, buffer, pitch);
+    }
+
     fn is_zero(value: u8) -> bool {
         if value == 0x00 {
             true
@@ -1141,6 +1218,18 @@ impl Dmg01Cpu {
     }
 
     /// stop
+    fn stop(&mut self) {
+        self.read_pc(); // 0x10 0x00
+        self.cycle += 4;
+
+        // cgb:0x01 prepared speed switch
+        if self.is_cgb && self.key1 & 0x01 == 0x01 {
+            self.key1 = (self.key1 ^ 0x80) & 0x80;
+        } else {
+            // low power mode:approximated by halt
+            self.set_halt(true);
+        }
+    }
 
     /// di
     fn di(&mut self) {
@@ -1407,6 +1496,7 @@ impl Dmg01Cpu {
             0xcb => self.cb_prefix(), // operation extention
 
             0x76 => self.halt(),
+            0x10 => self.stop(),
             _ => {
                 panic!("unexpected opecode {:#04x}", opecode)
             }
@@ -1448,7 +1538,12 @@ impl Dmg01Cpu {
 
         self.cycle = 0;
 
-        if self.get_halt() {
+        if self.dma_stall > 0 {
+            // cgb:stalled by the vram dma
+            let cycle: u16 = std::cmp::min(self.dma_stall, 4);
+            self.dma_stall -= cycle;
+            self.cycle += cycle as u8;
+        } else if self.get_halt() {
             self.cycle += 4;
         } else {
             self.fetch_execute();
@@ -1458,7 +1553,8 @@ impl Dmg01Cpu {
 
         self.update_device();
 
-        if self.interrupt_flag & self.interrupt_enable & 0x1f > 0 {
+        // the interrupts wait for the end of the dma
+        if self.dma_stall == 0 && self.interrupt_flag & self.interrupt_enable & 0x1f > 0 {
             self.set_halt(false);
             if self.get_ime() {
                 self.cycle = 0;
@@ -1471,7 +1567,26 @@ impl Dmg01Cpu {
             }
         }
 
-        total_cycle
+        // cgb double speed:the cpu cycles are counted at the normal speed
+        self.get_normal_speed_cycle(total_cycle)
+    }
+
+    fn get_normal_speed_cycle(&self, cycle: u8) -> u8 {
+        // 0x80:double speed
+        match self.key1 & 0x80 {
+            0x80 => cycle >> 1,
+            _ => cycle,
+        }
+    }
+
+    /// cgb:c000-cfff is bank 0, d000-dfff is bank 1-7
+    fn get_ram_addr(&self, address: u16) -> usize {
+        let bank: usize = match address & 0x1000 {
+            0x1000 if self.is_cgb => std::cmp::max(self.svbk & 0x07, 1) as usize,
+            0x1000 => 1,
+            _ => 0,
+        };
+        (bank << 12) | (address & 0x0fff) as usize
     }
 
     /// LCD OAM DMA Transfers
@@ -1489,6 +1604,42 @@ impl Dmg01Cpu {
         }
     }
 
+    /// CGB VRAM DMA Transfers:16 bytes per block, 8us each
+    fn transfer_hdma_block(&mut self) {
+        for _ in 0..0x10 {
+            let value = self.read_via_map(self.hdma_source);
+            self.write_via_map(0x8000 | (self.hdma_destination & 0x1fff), value);
+            self.hdma_source = self.hdma_source.wrapping_add(1);
+            self.hdma_destination = self.hdma_destination.wrapping_add(1);
+        }
+
+        // 0x80:double speed
+        self.dma_stall += match self.key1 & 0x80 {
+            0x80 => 64,
+            _ => 32,
+        };
+        self.hdma5 = self.hdma5.wrapping_sub(1); // 0x00 -> 0xff:finished
+    }
+
+    /// 0x80:h-blank dma, 0x00:general purpose dma
+    fn start_hdma(&mut self, value: u8) {
+        if self.hdma5 & 0x80 == 0 && value & 0x80 == 0 {
+            // stop the active h-blank dma
+            self.hdma5 |= 0x80;
+            return;
+        }
+
+        self.hdma5 = value & 0x7f;
+        if value & 0x80 == 0 {
+            while self.hdma5 != 0xff {
+                self.transfer_hdma_block();
+            }
+        } else if !self.ppu.is_lcd_enabled() {
+            // lcd off:the first block is transferred at once
+            self.transfer_hdma_block();
+        }
+    }
+
     /// Use the memory map
     fn read_via_map(&self, address: u16) -> u8 {
         Log::io(
@@ -1500,8 +1651,8 @@ impl Dmg01Cpu {
             0x0000..=0x7fff => self.cartridge.read(address),
             0x8000..=0x9fff => self.ppu.read(address), // vram
             0xa000..=0xbfff => self.cartridge.read(address),
-            0xc000..=0xdfff => self.ram[(address & 0x1fff) as usize],
-            0xe000..=0xfdff => self.ram[((address - 0x2000) & 0x1fff) as usize],
+            0xc000..=0xdfff => self.ram[self.get_ram_addr(address)],
+            0xe000..=0xfdff => self.ram[self.get_ram_addr(address - 0x2000)],
             0xfe00..=0xfe9f => self.ppu.read(address), // sprite
             0xfea0..=0xfeff => 0xff,                   // not usable
             0xff00 => self.joypad.read(address),
@@ -1509,6 +1660,10 @@ impl Dmg01Cpu {
             0xff0f => self.interrupt_flag,
             0xff10..=0xff26 => self.apu.read(address),
             0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.read(address), // lcd
+            0xff4d if self.is_cgb => self.key1 | 0x7e,
+            0xff4f | 0xff68..=0xff6b => self.ppu.read(address), // cgb vram bank and palettes
+            0xff55 if self.is_cgb => self.hdma5,
+            0xff70 if self.is_cgb => self.svbk | 0xf8,
             0xff80..=0xfffe => self.hram[(address & 0x007f) as usize],
             0xffff => self.interrupt_enable,
             _ => {
@@ -1538,8 +1693,8 @@ impl Dmg01Cpu {
             0x0000..=0x7fff => self.cartridge.write(address, value),
             0x8000..=0x9fff => self.ppu.write(address, value), // vram
             0xa000..=0xbfff => self.cartridge.write(address, value),
-            0xc000..=0xdfff => self.ram[(address & 0x1fff) as usize] = value,
-            0xe000..=0xfdff => self.ram[((address - 0x2000) & 0x1fff) as usize] = value,
+            0xc000..=0xdfff => self.ram[self.get_ram_addr(address)] = value,
+            0xe000..=0xfdff => self.ram[self.get_ram_addr(address - 0x2000)] = value,
             0xfe00..=0xfe9f => self.ppu.write(address, value), // sprite
             0xfea0..=0xfeff => (),                             // not usable
             0xff00 => self.joypad.write(address, value),
@@ -1548,6 +1703,24 @@ impl Dmg01Cpu {
             0xff10..=0xff3f => self.apu.write(address, value),
             0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.write(address, value), // lcd
             0xff46 => self.dma_transfer(value),
+            0xff4d if self.is_cgb => self.key1 = (self.key1 & 0x80) | (value & 0x01),
+            0xff4f | 0xff68..=0xff6b => self.ppu.write(address, value), // cgb vram bank and palettes
+            // cgb vram dma
+            0xff51 if self.is_cgb => {
+                self.hdma_source = (self.hdma_source & 0x00ff) | (value as u16) << 8
+            }
+            0xff52 if self.is_cgb => {
+                self.hdma_source = (self.hdma_source & 0xff00) | (value & 0xf0) as u16
+            }
+            0xff53 if self.is_cgb => {
+                self.hdma_destination =
+                    (self.hdma_destination & 0x00ff) | ((value & 0x1f) as u16) << 8
+            }
+            0xff54 if self.is_cgb => {
+                self.hdma_destination = (self.hdma_destination & 0xff00) | (value & 0xf0) as u16
+            }
+            0xff55 if self.is_cgb => self.start_hdma(value),
+            0xff70 if self.is_cgb => self.svbk = value & 0x07,
             0xff80..=0xfffe => self.hram[(address & 0x007f) as usize] = value,
             0xffff => self.interrupt_enable = value,
             _ => {
@@ -1557,14 +1730,34 @@ impl Dmg01Cpu {
     }
 
     fn update_device(&mut self) {
-        self.ppu.update(self.cycle);
+        // cgb double speed:the timer follows the cpu
+        let normal_cycle: u8 = self.get_normal_speed_cycle(self.cycle);
+        self.ppu.update(normal_cycle);
         self.timer.update(self.cycle);
+        self.cartridge.update(normal_cycle);
+
+        if let Some(sgb) = self.sgb.as_mut() {
+            while let Some(packet) = self.joypad.packets.pop_front() {
+                sgb.receive(packet, &self.ppu, &mut self.joypad);
+            }
+            if self.ppu.irq_vblank {
+                sgb.render(&self.ppu);
+            }
+        }
 
         if self.ppu.irq_vblank {
             self.interrupt_flag |= 0x01;
             self.ppu.irq_vblank = false;
         }
 
+        if self.ppu.hblank {
+            self.ppu.hblank = false;
+            // 0x80:inactive
+            if self.hdma5 & 0x80 == 0 {
+                self.transfer_hdma_block();
+            }
+        }
+
         if self.ppu.irq_lcdc {
             self.interrupt_flag |= 0x02;
             self.ppu.irq_lcdc = false;
@@ -1,57 +1,356 @@
+mod huc1;
+mod huc3;
+mod m161;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod pocket_camera;
+mod rocket;
+mod sachen;
+mod tama5;
+mod wisdom_tree;
 
 use super::super::Common;
 use super::Log;
+use huc1::HuC1;
+use huc3::HuC3;
+use m161::M161;
 use mbc1::MBC1;
 use mbc2::MBC2;
 use mbc3::MBC3;
 use mbc5::MBC5;
-use std::fs::File;
+use mbc6::MBC6;
+use mbc7::MBC7;
+use mmm01::MMM01;
+use pocket_camera::PocketCamera;
+use rocket::Rocket;
+use sachen::Sachen;
+use std::fs::{self, File};
 use std::io::{Read, Write};
+use std::ops::RangeInclusive;
 use std::path::Path;
+use std::time::SystemTime;
+use tama5::TAMA5;
+use wisdom_tree::WisdomTree;
+
+/// Memory bank controller
+pub trait Mbc {
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8;
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]);
+
+    /// called with the elapsed cpu cycles
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// extra battery backed state (rtc etc.) appended to the ram file
+    fn serialize(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn deserialize(&mut self, _data: &[u8]) {}
+
+    /// legacy clock file (.rtc)
+    fn import_rtc_file(&mut self, _data: &[u8]) {}
+
+    /// header address as the boot rom reads it (cgb and sgb flags)
+    fn get_header_address(&self, address: u16) -> u16 {
+        address
+    }
+
+    /// battery backed ram, rtc or flash changed since the last call,
+    /// the mappers keep it in a dirty field that is cleared here
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
+
+    /// rumble motor duty cycle since the last call, 0.0-1.0
+    fn get_rumble(&mut self) -> f32 {
+        0.0
+    }
+
+    /// accelerometer input, -1.0 to 1.0 (1g) for each axis
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// image file for the camera sensor
+    fn set_camera_image(&mut self, _data: &[u8]) {}
+}
+
+const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// rom bank (16KB) access wrapped to the rom size
+fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    const BANK_SIZE: usize = 16 * 1024;
+    let banks: usize = std::cmp::max(rom.len() / BANK_SIZE, 1);
+
+    let offset: usize = BANK_SIZE * (bank % banks);
+    rom[(address & 0x3fff) as usize + offset]
+}
+
+/// unix time of the host for the rtc footers, 0 before 1970
+fn get_current_time_sec() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(result) => result.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+/// seconds since the saved unix time, the host clock may have been set back
+fn get_elapsed_time_sec(last_time_sec: u64) -> u64 {
+    get_current_time_sec().saturating_sub(last_time_sec)
+}
+
+/// ram bank (8KB) address wrapped to the ram size
+fn get_ram_address(ram: &[u8], bank: usize, address: u16, log_mode: u8) -> usize {
+    const BANK_SIZE: usize = 8 * 1024;
+
+    let offset: usize = BANK_SIZE * bank;
+    Log::rom(format!("{: <15}:{:#04x}", "offset", offset), log_mode);
+    let ram_address = ((address & 0x1fff) as usize + offset) % ram.len();
+    Log::rom(
+        format!("{: <15}:{:#04x}", "ram address", ram_address),
+        log_mode,
+    );
+    ram_address
+}
+
+/// 0xff when the cartridge has no ram
+fn read_ram_bank(ram: &[u8], bank: usize, address: u16, log_mode: u8) -> u8 {
+    if ram.is_empty() {
+        return 0xff;
+    }
+    ram[get_ram_address(ram, bank, address, log_mode)]
+}
+
+/// ignored when the cartridge has no ram, true when the value is changed
+fn write_ram_bank(ram: &mut [u8], bank: usize, address: u16, value: u8, log_mode: u8) -> bool {
+    if ram.is_empty() {
+        return false;
+    }
+    let ram_address: usize = get_ram_address(ram, bank, address, log_mode);
+    let is_changed: bool = ram[ram_address] != value;
+    ram[ram_address] = value;
+    is_changed
+}
+
+/// features of the cartridge type (0x0147)
+#[derive(Copy, Clone)]
+pub struct Features {
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl Features {
+    pub fn new(cartridge_type: u8) -> Self {
+        // (ram, battery, timer, rumble)
+        let (ram, battery, timer, rumble): (bool, bool, bool, bool) = match cartridge_type {
+            0x02 | 0x08 | 0x0c | 0x12 | 0x1a => (true, false, false, false),
+            0x03 | 0x09 | 0x0d | 0x13 | 0x1b | 0x20 | 0xfc | 0xff => (true, true, false, false),
+            0x05 => (true, false, false, false), // mbc2 built-in ram
+            0x06 => (true, true, false, false),  // mbc2 built-in ram
+            0x0f => (false, true, true, false),
+            0x10 | 0xfe => (true, true, true, false),
+            0x1c => (false, false, false, true),
+            0x1d => (true, false, false, true),
+            0x1e | 0x22 => (true, true, false, true),
+            0xfd => (true, true, true, false), // tama5 eeprom
+            _ => (false, false, false, false),
+        };
+
+        Features {
+            ram,
+            battery,
+            timer,
+            rumble,
+        }
+    }
+}
+
+struct MbcEntry {
+    types: RangeInclusive<u8>,
+    detect: Option<fn(&[u8]) -> bool>, // heuristic for carts without a proper header
+    name: &'static str,
+    create: fn(u8, Features, &[u8]) -> Box<dyn Mbc>, // (log_mode, features, rom)
+}
+
+const MBC_REGISTRY: [MbcEntry; 17] = [
+    MbcEntry {
+        types: 0x00..=0x00,
+        detect: None,
+        name: "NONE",
+        create: |log_mode, _, rom| Box::new(MBC1::new(log_mode, rom)),
+    },
+    MbcEntry {
+        types: 0x01..=0x03,
+        detect: None,
+        name: "MBC1",
+        create: |log_mode, _, rom| Box::new(MBC1::new(log_mode, rom)),
+    },
+    MbcEntry {
+        types: 0x05..=0x06,
+        detect: None,
+        name: "MBC2",
+        create: |log_mode, _, _| Box::new(MBC2::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x10..=0x10,
+        detect: Some(M161::detect),
+        name: "M161",
+        create: |log_mode, _, _| Box::new(M161::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x0b..=0x0d,
+        detect: None,
+        name: "MMM01",
+        create: |log_mode, _, _| Box::new(MMM01::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x0f..=0x13,
+        detect: None,
+        name: "MBC3",
+        create: |log_mode, features, _| Box::new(MBC3::new(log_mode, features)),
+    },
+    MbcEntry {
+        types: 0x19..=0x1e,
+        detect: None,
+        name: "MBC5",
+        create: |log_mode, features, _| Box::new(MBC5::new(log_mode, features)),
+    },
+    MbcEntry {
+        types: 0x20..=0x20,
+        detect: None,
+        name: "MBC6",
+        create: |log_mode, _, _| Box::new(MBC6::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x22..=0x22,
+        detect: None,
+        name: "MBC7",
+        create: |log_mode, _, _| Box::new(MBC7::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0xfd..=0xfd,
+        detect: None,
+        name: "TAMA5",
+        create: |log_mode, _, _| Box::new(TAMA5::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0xfc..=0xfc,
+        detect: None,
+        name: "CAMERA",
+        create: |log_mode, _, _| Box::new(PocketCamera::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0xfe..=0xfe,
+        detect: None,
+        name: "HuC3",
+        create: |log_mode, _, _| Box::new(HuC3::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0xff..=0xff,
+        detect: None,
+        name: "HuC1",
+        create: |log_mode, _, _| Box::new(HuC1::new(log_mode)),
+    },
+    // unlicensed:the header is unreliable, any type
+    MbcEntry {
+        types: 0x00..=0xff,
+        detect: Some(WisdomTree::detect),
+        name: "WISDOM TREE",
+        create: |log_mode, _, _| Box::new(WisdomTree::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x00..=0xff,
+        detect: Some(Sachen::detect_mmc1),
+        name: "SACHEN MMC1",
+        create: |log_mode, _, _| Box::new(Sachen::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x00..=0xff,
+        detect: Some(Sachen::detect_mmc2),
+        name: "SACHEN MMC2",
+        create: |log_mode, _, _| Box::new(Sachen::new(log_mode)),
+    },
+    MbcEntry {
+        types: 0x00..=0xff,
+        detect: Some(Rocket::detect),
+        name: "ROCKET",
+        create: |log_mode, _, _| Box::new(Rocket::new(log_mode)),
+    },
+];
 
 pub struct Cartridge {
     log_mode: u8,
-    counter: u8, // FPS
     pub rom: Vec<u8>,
     pub ram: Vec<u8>,
     romfile: String,
-    cartridge_type: u8,
-    mbc1: MBC1,
-    mbc2: MBC2,
-    mbc3: MBC3,
-    mbc5: MBC5,
+    features: Features,
+    mbc: Box<dyn Mbc>,
+    dirty: bool,       // ram written since the last save
+    save_counter: u32, // cpu 4194304 Hz
 }
 
 impl Cartridge {
     pub fn new(log_mode: u8, romfile: String) -> Self {
         let rom_data: Vec<u8>;
         let ram_data: Vec<u8>;
-        let ramfile: String = romfile.clone() + ".sav";
+        let ramfile: String = romfile.clone() + Common::RAM_FILE_EXTENSION;
 
         rom_data = Self::load_file(romfile.clone());
         Log::info(format!("{: <5}:{} byte", "Size", rom_data.len()), log_mode);
 
-        let cartridge_type: u8 = rom_data[0x0147];
-        match cartridge_type {
-            0x00 => Log::info(format!("{: <5}:{}", "Type", "NONE"), log_mode),
-            0x01..=0x03 => Log::info(format!("{: <5}:{}", "Type", "MBC1"), log_mode),
-            0x05..=0x06 => Log::info(format!("{: <5}:{}", "Type", "MBC2"), log_mode),
-            0x0f..=0x13 => Log::info(format!("{: <5}:{}", "Type", "MBC3"), log_mode),
-            0x19..=0x1e => Log::info(format!("{: <5}:{}", "Type", "MBC5"), log_mode),
-            _ => {
+        let header: &[u8] = &rom_data[Self::get_header_offset(&rom_data)..];
+        let cartridge_type: u8 = header[0x0147];
+        // detected only for the listed types
+        let detected: Option<&MbcEntry> = MBC_REGISTRY.iter().find(|entry| {
+            entry.types.contains(&cartridge_type)
+                && entry.detect.is_some_and(|detect| detect(&rom_data))
+        });
+        let entry: &MbcEntry = match detected.or_else(|| {
+            MBC_REGISTRY
+                .iter()
+                .find(|entry| entry.detect.is_none() && entry.types.contains(&cartridge_type))
+        }) {
+            Some(result) => result,
+            None => {
                 Log::info(format!("{: <5}:{:#04x}", "Type", cartridge_type), log_mode);
                 panic!("unsupported type {:#04x}", cartridge_type);
             }
-        }
+        };
+        Log::info(format!("{: <5}:{}", "Type", entry.name), log_mode);
 
-        let ram_size: usize = match rom_data[0x0149] {
+        let features: Features = match detected {
+            Some(_) => Features::new(0x00), // no ram and battery
+            _ => Features::new(cartridge_type),
+        };
+        Log::info(
+            format!(
+                "{: <5}:ram={} battery={} timer={} rumble={}",
+                "Feat", features.ram, features.battery, features.timer, features.rumble
+            ),
+            log_mode,
+        );
+        let mut mbc: Box<dyn Mbc> = (entry.create)(log_mode, features, &rom_data);
+        let cgb_flag: u8 = Self::read_header(&rom_data, mbc.as_ref(), 0x0143);
+        let sgb_flag: u8 = Self::read_header(&rom_data, mbc.as_ref(), 0x0146);
+        Log::info(format!("{: <5}:{:#04x}", "CGB", cgb_flag), log_mode);
+        Log::info(format!("{: <5}:{:#04x}", "SGB", sgb_flag), log_mode);
+
+        let ram_size: usize = match header[0x0149] {
+            _ if !features.ram => 0,
             0 => {
                 match cartridge_type {
-                    0x05..=0x06 => 512, // mbc2
+                    0x05..=0x06 => 512,   // mbc2
+                    0x20 => 8 * 4 * 1024, // mbc6 8 banks of 4KB
+                    0x22 => 256,          // mbc7 eeprom
+                    0xfd => 32,           // tama5 eeprom
                     _ => 0,
                 }
             }
@@ -63,41 +362,72 @@ impl Cartridge {
             _ => panic!("unsupported ram size"),
         };
 
+        let mut footer: Vec<u8> = Vec::new();
+        let bakfile: String = ramfile.clone() + Common::BACKUP_FILE_EXTENSION;
+        let ramfile: String = match Path::new(&ramfile).exists() {
+            true => ramfile,
+            _ => bakfile, // fall back to the backup
+        };
         let ram_path: &Path = Path::new(&ramfile);
-        if ram_path.exists() {
+        if features.battery && ram_path.exists() {
             Log::info(format!("{: <5}:{}", "RAM", ramfile), log_mode);
-            ram_data = Self::load_file(ramfile.clone());
+            let mut data: Vec<u8> = Self::load_file(ramfile.clone());
+            if data.len() > ram_size {
+                // rtc footer (bgb/vba format) or flash
+                footer = data.split_off(ram_size);
+            }
+            data.resize(ram_size, 0);
+            ram_data = data;
         } else {
             Log::info(format!("{: <5}:", "RAM"), log_mode);
             ram_data = vec![0; ram_size];
         }
         Log::info(format!("{: <5}:{} byte", "SIZE", ram_data.len()), log_mode);
 
-        let mut mbc3 = MBC3::new(log_mode);
-        if cartridge_type == 0x0f || cartridge_type == 0x10 {
-            // MBC3+TIMER
-            let rtcfile = romfile.clone() + Common::RTC_FILE_EXTENSION;
-            let rtc_path: &Path = Path::new(&rtcfile);
-            if rtc_path.exists() {
-                Log::info(format!("{: <5}:{}", "RTC", rtcfile), log_mode);
-                mbc3.load_rtc(rtcfile);
-            } else {
-                Log::info(format!("{: <5}:", "RTC"), log_mode);
-            }
+        let rtcfile = romfile.clone() + Common::RTC_FILE_EXTENSION;
+        let rtc_path: &Path = Path::new(&rtcfile);
+        if !footer.is_empty() {
+            Log::info(format!("{: <5}:{} byte", "Extra", footer.len()), log_mode);
+            mbc.deserialize(&footer);
+        } else if features.battery && rtc_path.exists() {
+            // legacy text format
+            Log::info(format!("{: <5}:{}", "RTC", rtcfile), log_mode);
+            mbc.import_rtc_file(&Self::load_file(rtcfile.clone()));
+        }
+
+        let camerafile = romfile.clone() + Common::CAMERA_FILE_EXTENSION;
+        if cartridge_type == 0xfc && Path::new(&camerafile).exists() {
+            Log::info(format!("{: <5}:{}", "Cam", camerafile), log_mode);
+            mbc.set_camera_image(&Self::load_file(camerafile.clone()));
         }
 
         Cartridge {
             log_mode,
-            counter: 0,
             rom: rom_data,
             ram: ram_data,
             romfile,
-            cartridge_type,
-            mbc1: MBC1::new(log_mode),
-            mbc2: MBC2::new(log_mode),
-            mbc3,
-            mbc5: MBC5::new(log_mode),
+            features,
+            mbc,
+            dirty: false,
+            save_counter: 0,
+        }
+    }
+
+    /// MMM01 has the header of the menu in the last 32KB
+    fn get_header_offset(rom: &[u8]) -> usize {
+        if rom.len() > 0x8000 {
+            let offset: usize = rom.len() - 0x8000;
+            let has_logo: bool = rom[offset + 0x0104..offset + 0x0134] == rom[0x0104..0x0134];
+            if has_logo && (0x0b..=0x0d).contains(&rom[offset + 0x0147]) {
+                return offset;
+            }
         }
+        0
+    }
+
+    /// header byte through the mapper (scrambled headers)
+    fn read_header(rom: &[u8], mbc: &dyn Mbc, address: u16) -> u8 {
+        rom[Self::get_header_offset(rom) + mbc.get_header_address(address) as usize]
     }
 
     fn load_file(file: String) -> Vec<u8> {
@@ -115,36 +445,67 @@ impl Cartridge {
         data
     }
 
-    pub fn execute(&mut self) {
-        if self.cartridge_type == 0x0f || self.cartridge_type == 0x10 {
-            // MBC3+TIMER
-            self.counter += 1;
-            if self.counter >= Common::FPS {
-                self.counter = 0;
-                self.mbc3.exec_rtc();
+    /// write to a temporary file and rename it, the previous file is kept as a backup
+    fn save_file(file: String, data: &[u8]) -> std::io::Result<()> {
+        let tmpfile: String = file.clone() + Common::TMP_FILE_EXTENSION;
+        let bakfile: String = file.clone() + Common::BACKUP_FILE_EXTENSION;
+
+        let mut tmp: File = File::create(&tmpfile)?;
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+
+        if Path::new(&file).exists() {
+            fs::copy(&file, bakfile)?;
+        }
+        fs::rename(tmpfile, file)
+    }
+
+    pub fn update(&mut self, cycles: u8) {
+        self.mbc.tick(cycles);
+
+        self.save_counter += cycles as u32;
+        if self.save_counter >= Common::AUTOSAVE_INTERVAL * Common::CPU_CLOCK {
+            self.save_counter = 0;
+            if self.dirty {
+                self.save();
             }
         }
     }
 
-    pub fn save(self) {
-        let ramfile = self.romfile.clone() + Common::RAM_FILE_EXTENSION;
-        Log::info(format!("{: <5}:{}", "Save", ramfile), self.log_mode);
+    /// header 0x0143 0x80:cgb enhanced, 0xc0:cgb only
+    pub fn is_cgb(&self) -> bool {
+        Self::read_header(&self.rom, self.mbc.as_ref(), 0x0143) & 0x80 == 0x80
+    }
 
-        let mut file: File = match File::create(ramfile) {
-            Ok(result) => result,
-            Err(error) => panic!("file create error:{}", error),
-        };
+    /// header 0x0146 0x03:sgb functions, the boot rom also needs 0x014b 0x33:new licensee code
+    pub fn is_sgb(&self) -> bool {
+        Self::read_header(&self.rom, self.mbc.as_ref(), 0x0146) == 0x03
+            && Self::read_header(&self.rom, self.mbc.as_ref(), 0x014b) == 0x33
+    }
 
-        match file.write_all(&self.ram) {
-            Ok(result) => result,
-            Err(error) => panic!("file write error:{}", error),
-        };
+    /// called once per frame
+    pub fn get_rumble(&mut self) -> f32 {
+        self.mbc.get_rumble()
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn save(&mut self) {
+        if !self.features.battery {
+            // the ram is lost at power off
+            return;
+        }
 
-        if self.cartridge_type == 0x0f || self.cartridge_type == 0x10 {
-            // MBC3+TIMER
-            let rtcfile = self.romfile.clone() + Common::RTC_FILE_EXTENSION;
-            Log::info(format!("{: <5}:{}", "Save", rtcfile), self.log_mode);
-            self.mbc3.save_rtc(rtcfile);
+        let ramfile = self.romfile.clone() + Common::RAM_FILE_EXTENSION;
+        Log::info(format!("{: <5}:{}", "Save", ramfile), self.log_mode);
+
+        let mut data: Vec<u8> = self.ram.clone();
+        data.extend(self.mbc.serialize());
+        match Self::save_file(ramfile, &data) {
+            Ok(_) => self.dirty = false,
+            Err(error) => println!("save error:{}", error), // retry at the next save
         }
     }
 
@@ -155,12 +516,9 @@ impl Cartridge {
         );
         Log::rom(format!("{: <15}:{:#04x}", "value", value), self.log_mode);
 
-        match self.cartridge_type {
-            0x00..=0x03 => self.mbc1.write(address, value, &mut self.ram),
-            0x05..=0x06 => self.mbc2.write(address, value, &mut self.ram),
-            0x0f..=0x13 => self.mbc3.write(address, value, &mut self.ram),
-            0x19..=0x1e => self.mbc5.write(address, value, &mut self.ram),
-            _ => panic!("unsupported type {:#04x}", self.cartridge_type),
+        self.mbc.write(address, value, &mut self.ram);
+        if self.mbc.take_dirty() {
+            self.dirty = true; // ram, rtc or flash
         }
     }
 
@@ -170,13 +528,7 @@ impl Cartridge {
             self.log_mode,
         );
 
-        let result: u8 = match self.cartridge_type {
-            0x00..=0x03 => self.mbc1.read(address, &self.rom, &self.ram),
-            0x05..=0x06 => self.mbc2.read(address, &self.rom, &self.ram),
-            0x0f..=0x13 => self.mbc3.read(address, &self.rom, &self.ram),
-            0x19..=0x1e => self.mbc5.read(address, &self.rom, &self.ram),
-            _ => panic!("unsupported type {:#04x}", self.cartridge_type),
-        };
+        let result: u8 = self.mbc.read(address, &self.rom, &self.ram);
 
         Log::rom(format!("{: <15}:{:#04x}", "result", result), self.log_mode);
         result
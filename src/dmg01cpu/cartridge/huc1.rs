@@ -0,0 +1,70 @@
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Log, Mbc};
+
+/* HuC1 with infrared port */
+pub struct HuC1 {
+    log_mode: u8,
+    ir_mode: bool, // a000-bfff:ram or ir
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_led: bool, // transmitter on/off
+    dirty: bool,
+}
+
+impl HuC1 {
+    pub fn new(log_mode: u8) -> Self {
+        HuC1 {
+            log_mode,
+            ir_mode: false,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            ir_led: false,
+            dirty: false,
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => self.ir_mode = value & 0x0f == 0x0e, // 0x0e:ir, others:ram
+            0x2000..=0x3fff => self.rom_bank = value & 0x3f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x03,
+            0x6000..=0x7fff => (), // no register
+            0xa000..=0xbfff => {
+                if self.ir_mode {
+                    self.ir_led = value & 0x01 == 0x01;
+                    Log::rom(format!("{: <15}:{}", "ir led", self.ir_led), self.log_mode);
+                } else {
+                    self.dirty |=
+                        write_ram_bank(ram, self.ram_bank as usize, address, value, self.log_mode);
+                }
+            }
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.rom_bank as usize, address),
+
+            0xa000..=0xbfff => {
+                if self.ir_mode {
+                    0xc0 // 0xc0:no light, 0xc1:light received
+                } else {
+                    read_ram_bank(ram, self.ram_bank as usize, address, self.log_mode)
+                }
+            }
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
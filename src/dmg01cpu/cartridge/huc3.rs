@@ -0,0 +1,208 @@
+use super::{
+    get_current_time_sec, get_elapsed_time_sec, read_ram_bank, read_rom_bank, write_ram_bank,
+    Common, Log, Mbc,
+};
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+const RTC_FOOTER_SIZE: usize = 20;
+
+/* HuC3 with rtc and infrared port */
+pub struct HuC3 {
+    log_mode: u8,
+    mode: u8, // a000-bfff select
+    rom_bank: u8,
+    ram_bank: u8,
+    /* RTC */
+    counter: u32, // cpu 4194304 Hz, sub-second
+    sec: u32,
+    minutes: u32,            // 0-1439
+    days: u32,               // 12bits
+    rtc_memory: [u8; 0x100], // nibbles accessed by the command register
+    rtc_index: u8,
+    rtc_result: u8,
+    /* IR */
+    ir_led: bool, // transmitter on/off
+    dirty: bool,
+}
+
+impl HuC3 {
+    pub fn new(log_mode: u8) -> Self {
+        HuC3 {
+            log_mode,
+            mode: 0x00,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            counter: 0,
+            sec: 0,
+            minutes: 0,
+            days: 0,
+            rtc_memory: [0; 0x100],
+            rtc_index: 0x00,
+            rtc_result: 0x00,
+            ir_led: false,
+            dirty: false,
+        }
+    }
+
+    fn add_rtc_seconds(&mut self, seconds: u64) {
+        let total: u64 = (self.days as u64 * MINUTES_PER_DAY + self.minutes as u64) * 60
+            + self.sec as u64
+            + seconds;
+
+        self.sec = (total % 60) as u32;
+        self.minutes = (total / 60 % MINUTES_PER_DAY) as u32;
+        self.days = (total / 60 / MINUTES_PER_DAY) as u32 & 0x0fff;
+    }
+
+    /// time to rtc memory 0x00-0x05:minutes(12bits) and days(12bits), lower nibble first
+    fn latch_rtc(&mut self) {
+        for i in 0..3 {
+            self.rtc_memory[i] = (self.minutes >> (i * 4)) as u8 & 0x0f;
+            self.rtc_memory[i + 3] = (self.days >> (i * 4)) as u8 & 0x0f;
+        }
+    }
+
+    /// rtc memory 0x00-0x05 to time
+    fn set_rtc(&mut self) {
+        let mut minutes: u32 = 0;
+        let mut days: u32 = 0;
+        for i in 0..3 {
+            minutes |= (self.rtc_memory[i] as u32) << (i * 4);
+            days |= (self.rtc_memory[i + 3] as u32) << (i * 4);
+        }
+
+        self.sec = 0;
+        self.counter = 0;
+        self.minutes = minutes % MINUTES_PER_DAY as u32;
+        self.days = days;
+    }
+
+    fn execute_command(&mut self, value: u8) {
+        let argument: u8 = value & 0x0f;
+
+        // 0x70:command
+        match value & 0x70 {
+            0x10 => {
+                // read and increment
+                self.rtc_result = (value & 0xf0) | self.rtc_memory[self.rtc_index as usize];
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            }
+            0x30 => {
+                // write and increment
+                self.rtc_memory[self.rtc_index as usize] = argument;
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            }
+            0x40 => self.rtc_index = (self.rtc_index & 0xf0) | argument,
+            0x50 => self.rtc_index = (self.rtc_index & 0x0f) | argument << 4,
+            0x60 => match argument {
+                0x00 => self.latch_rtc(),
+                0x01 => {
+                    self.set_rtc();
+                    self.dirty = true;
+                }
+                0x02 => self.rtc_result = (value & 0xf0) | 0x01, // status:ready
+                _ => (),
+            },
+            _ => Log::rom(
+                format!("{: <15}:{:#04x}", "rtc command", value),
+                self.log_mode,
+            ),
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => self.mode = value & 0x0f,
+            0x2000..=0x3fff => self.rom_bank = value & 0x7f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x03,
+            0x6000..=0x7fff => (), // no register
+            0xa000..=0xbfff => match self.mode {
+                0x0a => {
+                    self.dirty |=
+                        write_ram_bank(ram, self.ram_bank as usize, address, value, self.log_mode)
+                }
+                0x0b => self.execute_command(value),
+                0x0d => (), // semaphore:commands are executed at once
+                0x0e => {
+                    self.ir_led = value & 0x01 == 0x01;
+                    Log::rom(format!("{: <15}:{}", "ir led", self.ir_led), self.log_mode);
+                }
+                _ => (), // 0x00:read only ram
+            },
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.rom_bank as usize, address),
+
+            0xa000..=0xbfff => match self.mode {
+                0x00 | 0x0a => read_ram_bank(ram, self.ram_bank as usize, address, self.log_mode),
+                0x0c => self.rtc_result,
+                0x0d => 0x01, // semaphore:ready
+                0x0e => 0xc0, // 0xc0:no light, 0xc1:light received
+                _ => 0xff,    // open bus
+            },
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.counter += cycles as u32;
+        if self.counter >= Common::CPU_CLOCK {
+            self.counter -= Common::CPU_CLOCK;
+            self.add_rtc_seconds(1);
+        }
+    }
+
+    /// sec, minutes, days (u32 little endian) and unix time (u64)
+    fn serialize(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(RTC_FOOTER_SIZE);
+        data.extend(self.sec.to_le_bytes());
+        data.extend(self.minutes.to_le_bytes());
+        data.extend(self.days.to_le_bytes());
+        data.extend(get_current_time_sec().to_le_bytes());
+        data
+    }
+
+    fn deserialize(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE {
+            Log::info(
+                format!("{: <5}:unexpected {} byte", "RTC", data.len()),
+                self.log_mode,
+            );
+            return;
+        }
+
+        let value = |index: usize| -> u32 {
+            u32::from_le_bytes([
+                data[index * 4],
+                data[index * 4 + 1],
+                data[index * 4 + 2],
+                data[index * 4 + 3],
+            ])
+        };
+        self.sec = value(0) % 60;
+        self.minutes = value(1) % MINUTES_PER_DAY as u32;
+        self.days = value(2) & 0x0fff;
+
+        let mut time: [u8; 8] = [0; 8];
+        time.copy_from_slice(&data[12..]);
+        let last_time_sec: u64 = u64::from_le_bytes(time);
+
+        self.add_rtc_seconds(get_elapsed_time_sec(last_time_sec));
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
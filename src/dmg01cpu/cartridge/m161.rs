@@ -0,0 +1,86 @@
+use super::{read_rom_bank, Mbc};
+
+/* M161 (Mani 4 in 1) */
+pub struct M161 {
+    rom_bank: u8, // 32KB bank
+    is_selected: bool,
+}
+
+impl M161 {
+    pub fn new(_log_mode: u8) -> Self {
+        M161 {
+            rom_bank: 0x00,
+            is_selected: false,
+        }
+    }
+
+    /// 256KB with MBC3+TIMER+RAM+BATTERY and no ram size
+    pub fn detect(rom: &[u8]) -> bool {
+        rom.len() == 256 * 1024 && rom[0x0147] == 0x10 && rom[0x0148] == 0x03 && rom[0x0149] == 0x00
+    }
+}
+
+impl Mbc for M161 {
+    fn write(&mut self, address: u16, value: u8, _ram: &mut [u8]) {
+        match address {
+            0x4000..=0x5fff => {
+                // bank latch:only the first write selects the game until reset
+                if !self.is_selected {
+                    self.rom_bank = value & 0x07;
+                    self.is_selected = true;
+                }
+            }
+            0x0000..=0x3fff | 0x6000..=0x7fff => (), // mbc3 ram enable etc.:ignored
+            0xa000..=0xbfff => (),                   // no ram
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], _ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x7fff => {
+                let bank: usize = (self.rom_bank as usize) << 1 | (address >> 14) as usize;
+                read_rom_bank(rom, bank, address)
+            }
+            0xa000..=0xbfff => 0xff, // no ram
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// each 32KB bank starts with its number
+    fn new_rom() -> Vec<u8> {
+        let mut rom: Vec<u8> = vec![0; 256 * 1024];
+        for bank in 0..8 {
+            rom[bank * 0x8000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn only_the_latch_selects_the_bank() {
+        let rom: Vec<u8> = new_rom();
+        let mut m161: M161 = M161::new(0);
+
+        // ram enable and stray writes
+        m161.write(0x0000, 0x0a, &mut []);
+        m161.write(0x2000, 0x03, &mut []);
+        m161.write(0x6000, 0x05, &mut []);
+        assert_eq!(m161.read(0x0000, &rom, &[]), 0);
+
+        m161.write(0x4000, 0x02, &mut []);
+        assert_eq!(m161.read(0x0000, &rom, &[]), 2);
+
+        // locked until reset
+        m161.write(0x4000, 0x06, &mut []);
+        assert_eq!(m161.read(0x0000, &rom, &[]), 2);
+    }
+}
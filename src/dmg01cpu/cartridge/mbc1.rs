@@ -1,37 +1,77 @@
-use super::Log;
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Log, Mbc};
 
 /* MBC1 or None */
 pub struct MBC1 {
     log_mode: u8,
-    bank_mode: u8, // rom or ram
+    multicart: bool, // MBC1M:upper bits are wired from bit 4
+    bank_mode: u8,   // rom or ram
     rom_bank: u8,
     shared_bank: u8, // ram bank or rom bank of upper 2bits
     enable_ram: u8,
+    dirty: bool,
 }
 
 impl MBC1 {
-    pub fn new(log_mode: u8) -> Self {
+    pub fn new(log_mode: u8, rom: &[u8]) -> Self {
+        let multicart = Self::is_multicart(rom);
+        if multicart {
+            Log::info(format!("{: <5}:{}", "Multi", "MBC1M"), log_mode);
+        }
+
         MBC1 {
             log_mode,
+            multicart,
             rom_bank: 0x00,
             shared_bank: 0x00,
             bank_mode: 0x00,
             enable_ram: 0x00,
+            dirty: false,
         }
     }
 
-    fn get_rom_bank(&self) -> u8 {
-        let bank_number: u8 = match self.bank_mode {
-            0x01 => self.rom_bank,                        // ram banking mode
-            _ => self.shared_bank & 0x60 | self.rom_bank, // 0x00:rom banking mode
-        };
+    /// multicarts are 8Mbit and have a nintendo logo at the top of each game (bank 0x10, 0x20, 0x30)
+    fn is_multicart(rom: &[u8]) -> bool {
+        const LOGO_START: usize = 0x0104;
+        const LOGO_END: usize = 0x0134;
+        const GAME_SIZE: usize = 0x10 * 0x4000;
+
+        if rom.len() != 4 * GAME_SIZE {
+            return false;
+        }
+
+        let logo = &rom[LOGO_START..LOGO_END];
+        (1..4).any(|game| &rom[game * GAME_SIZE + LOGO_START..game * GAME_SIZE + LOGO_END] == logo)
+    }
 
-        match bank_number {
-            0x00 | 0x20 | 0x40 | 0x60 => bank_number + 1, // specification
-            _ => bank_number,
+    fn get_upper_bank(&self) -> usize {
+        match self.multicart {
+            true => (self.shared_bank as usize) << 4,
+            _ => (self.shared_bank as usize) << 5,
         }
     }
 
+    fn get_rom_bank0(&self) -> usize {
+        match self.bank_mode {
+            0x01 => self.get_upper_bank(), // ram banking mode:remap 0000-3fff
+            _ => 0x00,                     // 0x00:rom banking mode
+        }
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        // 0x00 is checked before the upper bits are added (0x20 -> 0x21...)
+        let lower_bank: u8 = match self.rom_bank {
+            0x00 => 0x01, // specification
+            _ => self.rom_bank,
+        };
+
+        let lower_bank: usize = match self.multicart {
+            true => (lower_bank & 0x0f) as usize,
+            _ => lower_bank as usize,
+        };
+
+        self.get_upper_bank() | lower_bank
+    }
+
     fn is_ram_enabled(&self) -> bool {
         if self.enable_ram & 0x0f == 0x0a {
             true
@@ -46,31 +86,26 @@ impl MBC1 {
             _ => 0x00,                       // 0x00:rom banking mode
         }
     }
+}
 
-    pub fn write(&mut self, address: u16, value: u8, ram: &mut Vec<u8>) {
+impl Mbc for MBC1 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
         match address {
             0x0000..=0x1fff => self.enable_ram = value,
             0x2000..=0x3fff => self.rom_bank = value & 0x1f,
-            0x4000..=0x5fff => {
-                match self.bank_mode {
-                    0x01 => self.shared_bank = value & 0x03, // ram banking mode
-                    _ => self.shared_bank = (value & 0x03) << 5, // 0x00:rom banking mode
-                }
-            }
+            0x4000..=0x5fff => self.shared_bank = value & 0x03,
             0x6000..=0x7fff => self.bank_mode = value & 0x01,
             0xa000..=0xbfff => {
                 if self.is_ram_enabled() {
-                    let offset: usize = (8 * 1024) * self.get_ram_bank() as usize;
-                    Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                    let ram_address = (address & 0x1fff) as usize + offset;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
+                    self.dirty |= write_ram_bank(
+                        ram,
+                        self.get_ram_bank() as usize,
+                        address,
+                        value,
                         self.log_mode,
                     );
-                    ram[ram_address] = value;
-                } else {
-                    panic!("unexpected address {:#08x}.need return?", address)
                 }
+                // disabled:ignore
             }
             _ => {
                 panic!("write address error:{:#08x}", address)
@@ -78,29 +113,16 @@ impl MBC1 {
         }
     }
 
-    pub fn read(&self, address: u16, rom: &Vec<u8>, ram: &Vec<u8>) -> u8 {
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
         match address {
-            0x0000..=0x3fff => rom[address as usize],
-            0x4000..=0x7fff => {
-                let offset: usize = (16 * 1024) * self.get_rom_bank() as usize;
-                rom[(address & 0x3fff) as usize + offset]
-            }
+            0x0000..=0x3fff => read_rom_bank(rom, self.get_rom_bank0(), address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank(), address),
 
             0xa000..=0xbfff => {
                 if self.is_ram_enabled() {
-                    let offset: usize = (8 * 1024) * self.get_ram_bank() as usize;
-                    Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                    let ram_address = (address & 0x1fff) as usize + offset;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
-                        self.log_mode,
-                    );
-                    ram[ram_address]
+                    read_ram_bank(ram, self.get_ram_bank() as usize, address, self.log_mode)
                 } else {
-                    panic!(
-                        "unexpected address {:#08x}.ram is disabled.need 0xff?",
-                        address
-                    )
+                    0xff // disabled:open bus
                 }
             }
 
@@ -109,4 +131,8 @@ impl MBC1 {
             }
         }
     }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
 }
@@ -1,9 +1,11 @@
-use super::Log;
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
 
+/* MBC2 with built-in 512x4bit ram */
 pub struct MBC2 {
     log_mode: u8,
     rom_bank: u8,
     enable_ram: bool,
+    dirty: bool,
 }
 
 impl MBC2 {
@@ -11,43 +13,38 @@ impl MBC2 {
         MBC2 {
             log_mode,
             rom_bank: 0x01,
-            enable_ram: true,
+            enable_ram: false,
+            dirty: false,
         }
     }
 
     fn get_rom_bank(&self) -> u8 {
-        let bank: u8 = self.rom_bank & 0x0f;
-        match bank {
-            0x01..=0x0F => bank,
-            _ => {
-                panic!("rom bank error:{:#04x}", bank)
-            }
+        match self.rom_bank & 0x0f {
+            0x00 => 0x01, // specification
+            bank => bank,
         }
     }
+}
 
-    pub fn write(&mut self, address: u16, value: u8, ram: &mut Vec<u8>) {
+impl Mbc for MBC2 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
         match address {
-            0x0000..=0x1fff => {
+            0x0000..=0x3fff => {
+                // 0x0100:register select
                 if (address & 0x0100) == 0 {
-                    self.enable_ram = !(self.enable_ram);
-                }
-            }
-            0x2000..=0x3fff => {
-                if (address & 0x0100) == 0x0100 {
-                    self.rom_bank = value;
+                    self.enable_ram = value & 0x0f == 0x0a;
+                } else {
+                    self.rom_bank = value & 0x0f;
                 }
             }
-            0xa000..=0xa1ff => {
+            0x4000..=0x7fff => (), // no register
+            0xa000..=0xbfff => {
+                // 512 bytes are echoed, only the lower 4bits exist
                 if self.enable_ram {
-                    let ram_address = (address & 0x01ff) as usize;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
-                        self.log_mode,
-                    );
-                    ram[ram_address] = value;
-                } else {
-                    panic!("unexpected address {:#08x}.need return?", address)
+                    self.dirty |=
+                        write_ram_bank(ram, 0x00, address & 0x01ff, value & 0x0f, self.log_mode);
                 }
+                // disabled:ignore
             }
             _ => {
                 panic!("write address error:{:#08x}", address)
@@ -55,27 +52,17 @@ impl MBC2 {
         }
     }
 
-    pub fn read(&self, address: u16, rom: &Vec<u8>, ram: &Vec<u8>) -> u8 {
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
         match address {
-            0x0000..=0x3fff => rom[address as usize],
-            0x4000..=0x7fff => {
-                let offset: usize = (16 * 1024) * self.get_rom_bank() as usize;
-                rom[(address & 0x3fff) as usize + offset]
-            }
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank() as usize, address),
 
-            0xa000..=0xa1ff => {
+            0xa000..=0xbfff => {
+                // 512 bytes are echoed, the upper 4bits are open
                 if self.enable_ram {
-                    let ram_address = (address & 0x01ff) as usize;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
-                        self.log_mode,
-                    );
-                    ram[ram_address]
+                    read_ram_bank(ram, 0x00, address & 0x01ff, self.log_mode) | 0xf0
                 } else {
-                    panic!(
-                        "unexpected address {:#08x}.ram is disabled.need 0xff?",
-                        address
-                    )
+                    0xff // disabled:open bus
                 }
             }
 
@@ -84,4 +71,8 @@ impl MBC2 {
             }
         }
     }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
 }
@@ -1,14 +1,19 @@
-use super::Log;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
-use std::time::SystemTime;
+use super::{
+    get_current_time_sec, get_elapsed_time_sec, read_ram_bank, read_rom_bank, write_ram_bank,
+    Common, Features, Log, Mbc,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_32BIT: usize = 44;
 
 pub struct MBC3 {
     log_mode: u8,
+    timer: bool,
+    counter: u32, // cpu 4194304 Hz, sub-second
     enable_ram_rtc: u8,
     ram_rtc_status: u8,
     rom_bank: u8,
-    is_latch: bool,
     pre_latch: u8,
     latch_sec: u8,
     latch_min: u8,
@@ -20,17 +25,19 @@ pub struct MBC3 {
     min: u8,
     hour: u8,
     day_counter_low: u8,
-    day_counter_high: u8,
+    day_counter_high: u8, // 0x01:day counter bit 8, 0x40:halt, 0x80:day counter carry
+    dirty: bool,
 }
 
 impl MBC3 {
-    pub fn new(log_mode: u8) -> Self {
+    pub fn new(log_mode: u8, features: Features) -> Self {
         MBC3 {
             log_mode,
+            timer: features.timer,
+            counter: 0,
             enable_ram_rtc: 0x00,
             ram_rtc_status: 0x00,
             rom_bank: 0x00,
-            is_latch: false,
             pre_latch: 0x01,
             latch_sec: 0x00,
             latch_min: 0x00,
@@ -43,135 +50,148 @@ impl MBC3 {
             hour: 0x00,
             day_counter_low: 0x00,
             day_counter_high: 0x00,
+            dirty: false,
         }
     }
 
-    pub fn exec_rtc(&mut self) {
-        // 0x40 halt
-        if self.day_counter_high & 0x40 == 0x00 {
-            // active
-            self.sec += 1;
-            let mut day_counter: u16 =
-                (self.day_counter_high as u16) << 8 | self.day_counter_low as u16;
-
-            if self.sec > 0x3b {
-                self.sec = 0;
-                self.min += 1;
-            }
-            if self.min > 0x3b {
-                self.min = 0;
-                self.hour += 1;
-            }
-            if self.hour > 0x17 {
-                self.hour = 0;
-                day_counter += 1;
-            }
-            if day_counter & 0x03ff > 0x01ff {
-                self.day_counter_high = self.day_counter_high | 0x80; // counter overflow
-                self.day_counter_high = self.day_counter_high & 0xc0;
-                self.day_counter_low = 0x00;
-            } else {
-                self.day_counter_high = (day_counter >> 8) as u8 | self.day_counter_high;
-                self.day_counter_low = (day_counter & 0x00ff) as u8
-            }
+    fn is_rtc_halted(&self) -> bool {
+        self.day_counter_high & 0x40 == 0x40
+    }
+
+    /// one second, an invalid value counts up to its bit width overflow without a carry
+    fn step_rtc_second(&mut self) {
+        self.sec = (self.sec + 1) & 0x3f;
+        if self.sec != 60 {
+            return;
         }
+        self.sec = 0;
+        self.min = (self.min + 1) & 0x3f;
+        if self.min != 60 {
+            return;
+        }
+        self.min = 0;
+        self.hour = (self.hour + 1) & 0x1f;
+        if self.hour != 24 {
+            return;
+        }
+        self.hour = 0;
+        self.add_rtc_days(1);
     }
 
-    pub fn save_rtc(self, rtcfile: String) {
-        let mut file: File = match File::create(rtcfile) {
-            Ok(result) => result,
-            Err(error) => panic!("file create error:{}", error),
-        };
+    /// the day counter carry is sticky until written
+    fn add_rtc_days(&mut self, days: u64) {
+        let day_counter: u64 =
+            (((self.day_counter_high as u64 & 0x01) << 8) | self.day_counter_low as u64) + days;
+        if day_counter > 0x01ff {
+            self.day_counter_high |= 0x80; // counter overflow
+        }
+        self.day_counter_low = (day_counter & 0xff) as u8;
+        self.day_counter_high = (self.day_counter_high & 0xc0) | ((day_counter >> 8) & 0x01) as u8;
+    }
 
-        let current_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(result) => result,
-            Err(error) => panic!("current time error:{}", error),
-        };
+    /// advance the clock, stepped while a register is invalid, then arithmetic catch-up
+    fn add_rtc_seconds(&mut self, seconds: u64) {
+        if self.is_rtc_halted() {
+            return;
+        }
 
-        let lines = vec![
-            self.sec.to_string(),
-            self.min.to_string(),
-            self.hour.to_string(),
-            self.day_counter_low.to_string(),
-            self.day_counter_high.to_string(),
-            current_time.as_secs().to_string(),
+        let mut seconds: u64 = seconds;
+        while seconds > 0 && (self.sec >= 60 || self.min >= 60 || self.hour >= 24) {
+            self.step_rtc_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+
+        let total: u64 =
+            self.hour as u64 * 60 * 60 + self.min as u64 * 60 + self.sec as u64 + seconds;
+        self.sec = (total % 60) as u8;
+        self.min = (total / 60 % 60) as u8;
+        self.hour = (total / (60 * 60) % 24) as u8;
+        self.add_rtc_days(total / SECONDS_PER_DAY);
+    }
+
+    /// bgb/vba footer:current and latched registers (u32 little endian) and unix time (u64)
+    fn save_rtc(&self) -> Vec<u8> {
+        let registers: [u8; 10] = [
+            self.sec,
+            self.min,
+            self.hour,
+            self.day_counter_low,
+            self.day_counter_high,
+            self.latch_sec,
+            self.latch_min,
+            self.latch_hour,
+            self.latch_day_counter_low,
+            self.latch_day_counter_high,
         ];
 
-        for line in lines {
-            match writeln!(file, "{}", line) {
-                Ok(result) => result,
-                Err(error) => panic!("file write error:{}", error),
-            };
+        let mut data: Vec<u8> = Vec::with_capacity(RTC_FOOTER_SIZE);
+        for register in registers {
+            data.extend((register as u32).to_le_bytes());
         }
+        data.extend(get_current_time_sec().to_le_bytes());
+        data
     }
 
-    pub fn load_rtc(&mut self, rtcfile: String) {
-        let file: File = match File::open(rtcfile) {
-            Ok(result) => result,
-            Err(error) => panic!("file open error:{}", error),
-        };
+    /// 48 bytes footer, or 44 bytes with a 32bit unix time
+    fn load_rtc_footer(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != RTC_FOOTER_SIZE && data.len() != RTC_FOOTER_SIZE_32BIT {
+            return Err(format!("unexpected footer size {}", data.len()));
+        }
+        let value = |index: usize| -> u8 { data[index * 4] };
 
-        let mut reader = BufReader::new(file);
-        let mut buf = String::new();
+        self.sec = value(0) & 0x3f;
+        self.min = value(1) & 0x3f;
+        self.hour = value(2) & 0x1f;
+        self.day_counter_low = value(3);
+        self.day_counter_high = value(4) & 0xc1;
+        self.latch_sec = value(5) & 0x3f;
+        self.latch_min = value(6) & 0x3f;
+        self.latch_hour = value(7) & 0x1f;
+        self.latch_day_counter_low = value(8);
+        self.latch_day_counter_high = value(9) & 0xc1;
 
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                self.sec = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                self.min = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                self.hour = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                self.day_counter_low = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                self.day_counter_high = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
+        let mut time: [u8; 8] = [0; 8];
+        time[..data.len() - 40].copy_from_slice(&data[40..]);
+        let last_time_sec: u64 = u64::from_le_bytes(time);
 
-        let last_time_sec: u64;
-        match reader.read_line(&mut buf) {
-            Ok(_) => {
-                last_time_sec = buf.trim().parse().unwrap();
-                buf.clear();
-            }
-            Err(error) => panic!("file read error:{}", error),
-        }
+        self.add_rtc_seconds(get_elapsed_time_sec(last_time_sec));
+        Ok(())
+    }
 
-        let current_time = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+    /// legacy six lines text (.rtc)
+    fn load_rtc_text(&mut self, data: &[u8]) -> Result<(), String> {
+        let text: &str = match std::str::from_utf8(data) {
             Ok(result) => result,
-            Err(error) => panic!("current time error:{}", error),
+            Err(error) => return Err(error.to_string()),
         };
-        let current_time_sec: u64 = current_time.as_secs();
-        if current_time_sec >= last_time_sec {
-            let counter = current_time_sec - last_time_sec;
-            for _ in 0..counter {
-                self.exec_rtc();
-            }
-        } else {
-            panic!("last time error");
+        let values: Vec<u64> = match text.lines().map(|line| line.trim().parse()).collect() {
+            Ok(result) => result,
+            Err(error) => return Err(error.to_string()),
+        };
+        if values.len() < 6 {
+            return Err(format!("unexpected end of data, {} lines", values.len()));
         }
+
+        self.sec = values[0] as u8 & 0x3f;
+        self.min = values[1] as u8 & 0x3f;
+        self.hour = values[2] as u8 & 0x1f;
+        self.day_counter_low = values[3] as u8;
+        self.day_counter_high = values[4] as u8 & 0xc1;
+        let last_time_sec: u64 = values[5];
+
+        self.add_rtc_seconds(get_elapsed_time_sec(last_time_sec));
+        Ok(())
+    }
+
+    fn latch_rtc(&mut self) {
+        self.latch_sec = self.sec;
+        self.latch_min = self.min;
+        self.latch_hour = self.hour;
+        self.latch_day_counter_low = self.day_counter_low;
+        self.latch_day_counter_high = self.day_counter_high;
     }
 
     fn get_rom_bank(&self) -> u8 {
@@ -186,67 +206,53 @@ impl MBC3 {
     }
 
     fn is_ram_rtc_enabled(&self) -> bool {
-        match self.enable_ram_rtc {
-            0x00 => false,
-            0x0a => true,
-            _ => {
-                panic!(
-                    "unexpected value to enable ram and rtc{:#04x}",
-                    self.enable_ram_rtc
-                )
-            }
-        }
+        // 0x0a:enable, others:disable
+        self.enable_ram_rtc & 0x0f == 0x0a
     }
+}
 
-    pub fn write(&mut self, address: u16, value: u8, ram: &mut Vec<u8>) {
+impl Mbc for MBC3 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
         match address {
             0x0000..=0x1fff => self.enable_ram_rtc = value,
             0x2000..=0x3fff => self.rom_bank = value & 0x7f,
             0x4000..=0x5fff => self.ram_rtc_status = value,
             0x6000..=0x7fff => {
+                // 0x00 -> 0x01:latch clock data
                 if self.pre_latch == 0x00 && value == 0x01 {
-                    self.is_latch = !self.is_latch;
-                    if self.is_latch {
-                        self.latch_sec = self.sec;
-                        self.latch_min = self.min;
-                        self.latch_hour = self.hour;
-                        self.latch_day_counter_low = self.day_counter_low;
-                        self.latch_day_counter_high = self.day_counter_high;
-                    }
+                    self.latch_rtc();
                 }
                 self.pre_latch = value;
             }
             0xa000..=0xbfff => {
                 if self.is_ram_rtc_enabled() {
                     match self.ram_rtc_status {
-                        0x00..=0x03 => {
-                            let offset: usize = (8 * 1024) * self.ram_rtc_status as usize;
-                            Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                            let ram_address = (address & 0x1fff) as usize + offset;
-                            Log::rom(
-                                format!("{: <15}:{:#04x}", "ram address", ram_address),
+                        0x00..=0x07 => {
+                            self.dirty |= write_ram_bank(
+                                ram,
+                                self.ram_rtc_status as usize,
+                                address,
+                                value,
                                 self.log_mode,
-                            );
-                            ram[ram_address] = value;
+                            )
                         }
-                        0x08..=0x0c => {
-                            if self.is_latch == false {
-                                match self.ram_rtc_status {
-                                    0x08 => self.sec = value & 0x3b,
-                                    0x09 => self.min = value & 0x3b,
-                                    0x0a => self.hour = value & 0x17,
-                                    0x0b => self.day_counter_low = value,
-                                    _ => self.day_counter_high = value, // 0x0c
+                        0x08..=0x0c if self.timer => {
+                            match self.ram_rtc_status {
+                                0x08 => {
+                                    self.sec = value & 0x3f;
+                                    self.counter = 0; // reset sub-second
                                 }
+                                0x09 => self.min = value & 0x3f,
+                                0x0a => self.hour = value & 0x1f,
+                                0x0b => self.day_counter_low = value,
+                                _ => self.day_counter_high = value & 0xc1, // 0x0c
                             }
+                            self.dirty = true;
                         }
-                        _ => {
-                            panic!("unexpected ram rtc status {:#04x}.", self.ram_rtc_status)
-                        }
+                        _ => (), // unmapped:ignore
                     }
-                } else {
-                    panic!("unexpected address {:#08x}.need return?", address)
                 }
+                // disabled:ignore
             }
             _ => {
                 panic!("write address error:{:#08x}", address)
@@ -254,54 +260,28 @@ impl MBC3 {
         }
     }
 
-    pub fn read(&self, address: u16, rom: &Vec<u8>, ram: &Vec<u8>) -> u8 {
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
         match address {
-            0x0000..=0x3fff => rom[address as usize],
-            0x4000..=0x7fff => {
-                let offset: usize = (16 * 1024) * self.get_rom_bank() as usize;
-                rom[(address & 0x3fff) as usize + offset]
-            }
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank() as usize, address),
             0xa000..=0xbfff => {
                 if self.is_ram_rtc_enabled() {
                     match self.ram_rtc_status {
-                        0x00..=0x03 => {
-                            let offset: usize = (8 * 1024) * self.ram_rtc_status as usize;
-                            Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                            let ram_address = (address & 0x1fff) as usize + offset;
-                            Log::rom(
-                                format!("{: <15}:{:#04x}", "ram address", ram_address),
-                                self.log_mode,
-                            );
-                            ram[ram_address]
-                        }
-                        0x08..=0x0c => {
-                            if self.is_latch {
-                                match self.ram_rtc_status {
-                                    0x08 => self.latch_sec,
-                                    0x09 => self.latch_min,
-                                    0x0a => self.latch_hour,
-                                    0x0b => self.latch_day_counter_low,
-                                    _ => self.latch_day_counter_high, //0x0c
-                                }
-                            } else {
-                                match self.ram_rtc_status {
-                                    0x08 => self.sec,
-                                    0x09 => self.min,
-                                    0x0a => self.hour,
-                                    0x0b => self.day_counter_low,
-                                    _ => self.day_counter_high, // 0x0c
-                                }
-                            }
-                        }
-                        _ => {
-                            panic!("unexpected ram rtc status {:#04x}.", self.ram_rtc_status)
+                        0x00..=0x07 => {
+                            read_ram_bank(ram, self.ram_rtc_status as usize, address, self.log_mode)
                         }
+                        // unused bits read as 1
+                        0x08..=0x0c if self.timer => match self.ram_rtc_status {
+                            0x08 => self.latch_sec | 0xc0,
+                            0x09 => self.latch_min | 0xc0,
+                            0x0a => self.latch_hour | 0xe0,
+                            0x0b => self.latch_day_counter_low,
+                            _ => self.latch_day_counter_high | 0x3e, //0x0c
+                        },
+                        _ => 0xff, // unmapped:open bus
                     }
                 } else {
-                    panic!(
-                        "unexpected address {:#08x}.ram is disabled.need 0xff?",
-                        address
-                    )
+                    0xff // disabled:open bus
                 }
             }
 
@@ -310,4 +290,145 @@ impl MBC3 {
             }
         }
     }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.timer && !self.is_rtc_halted() {
+            self.counter += cycles as u32;
+            if self.counter >= Common::CPU_CLOCK {
+                self.counter -= Common::CPU_CLOCK;
+                self.add_rtc_seconds(1);
+            }
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        if self.timer {
+            self.save_rtc()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// a broken footer is ignored, the clock keeps the initial state
+    fn deserialize(&mut self, data: &[u8]) {
+        if self.timer {
+            if let Err(error) = self.load_rtc_footer(data) {
+                Log::info(format!("{: <5}:ignored {}", "RTC", error), self.log_mode);
+            }
+        }
+    }
+
+    fn import_rtc_file(&mut self, data: &[u8]) {
+        if self.timer {
+            if let Err(error) = self.load_rtc_text(data) {
+                Log::info(format!("{: <5}:ignored {}", "RTC", error), self.log_mode);
+            }
+        }
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_mbc3() -> MBC3 {
+        MBC3::new(0, Features::new(0x10))
+    }
+
+    /// select an rtc register and read the latched value
+    fn read_rtc(mbc3: &mut MBC3, register: u8) -> u8 {
+        let mut ram: [u8; 0] = [];
+        mbc3.write(0x0000, 0x0a, &mut ram);
+        mbc3.write(0x4000, register, &mut ram);
+        mbc3.write(0x6000, 0x00, &mut ram);
+        mbc3.write(0x6000, 0x01, &mut ram);
+        mbc3.read(0xa000, &[0; 0x8000], &ram)
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut mbc3: MBC3 = new_mbc3();
+        mbc3.add_rtc_seconds(3 * 60 * 60 + 2 * 60 + 1);
+
+        let mut loaded: MBC3 = new_mbc3();
+        loaded.deserialize(&mbc3.serialize());
+        assert_eq!(read_rtc(&mut loaded, 0x0a) & 0x1f, 3);
+        assert_eq!(read_rtc(&mut loaded, 0x09) & 0x3f, 2);
+    }
+
+    #[test]
+    fn truncated_footer_is_ignored() {
+        let mut source: MBC3 = new_mbc3();
+        source.add_rtc_seconds(5 * 60);
+        let footer: Vec<u8> = source.serialize();
+
+        let mut mbc3: MBC3 = new_mbc3();
+        mbc3.deserialize(&footer[..30]);
+        assert_eq!(read_rtc(&mut mbc3, 0x09) & 0x3f, 0);
+
+        // a footer is never parsed as the legacy text
+        mbc3.deserialize(b"1\n2\n3\n");
+        assert_eq!(read_rtc(&mut mbc3, 0x08) & 0x3f, 0);
+    }
+
+    #[test]
+    fn broken_rtc_file_is_ignored() {
+        let mut mbc3: MBC3 = new_mbc3();
+        mbc3.import_rtc_file(b"1\n2\nthree\n4\n5\n6\n");
+        mbc3.import_rtc_file(b"1\n2\n");
+        mbc3.import_rtc_file(&[0xff, 0xfe, 0x00]);
+        assert_eq!(read_rtc(&mut mbc3, 0x08) & 0x3f, 0);
+
+        mbc3.import_rtc_file(format!("7\n0\n0\n0\n0\n{}\n", get_current_time_sec()).as_bytes());
+        assert_eq!(read_rtc(&mut mbc3, 0x08) & 0x3f, 7);
+    }
+
+    #[test]
+    fn only_changes_are_dirty() {
+        let mut mbc3: MBC3 = new_mbc3();
+        let mut ram: [u8; 0x2000] = [0; 0x2000];
+
+        // disabled
+        mbc3.write(0xa000, 0x12, &mut ram);
+        assert!(!mbc3.take_dirty());
+
+        // the same value
+        mbc3.write(0x0000, 0x0a, &mut ram);
+        mbc3.write(0x4000, 0x00, &mut ram);
+        mbc3.write(0xa000, 0x00, &mut ram);
+        assert!(!mbc3.take_dirty());
+
+        mbc3.write(0xa000, 0x12, &mut ram);
+        assert!(mbc3.take_dirty());
+        assert!(!mbc3.take_dirty());
+    }
+
+    #[test]
+    fn invalid_values_overflow_without_carry() {
+        let mut mbc3: MBC3 = new_mbc3();
+        let mut ram: [u8; 0] = [];
+        mbc3.write(0x0000, 0x0a, &mut ram);
+        for (register, value) in [(0x08, 62), (0x09, 0), (0x0a, 31)] {
+            mbc3.write(0x4000, register, &mut ram);
+            mbc3.write(0xa000, value, &mut ram);
+        }
+
+        mbc3.add_rtc_seconds(2);
+        assert_eq!(read_rtc(&mut mbc3, 0x08) & 0x3f, 0);
+        assert_eq!(read_rtc(&mut mbc3, 0x09) & 0x3f, 0);
+        assert_eq!(read_rtc(&mut mbc3, 0x0a) & 0x1f, 31);
+
+        // a valid rollover carries
+        mbc3.add_rtc_seconds(60 * 60);
+        assert_eq!(read_rtc(&mut mbc3, 0x0a) & 0x1f, 0);
+        assert_eq!(read_rtc(&mut mbc3, 0x0b), 0);
+        mbc3.add_rtc_seconds(24 * 60 * 60 + 61);
+        assert_eq!(read_rtc(&mut mbc3, 0x08) & 0x3f, 1);
+        assert_eq!(read_rtc(&mut mbc3, 0x09) & 0x3f, 1);
+        assert_eq!(read_rtc(&mut mbc3, 0x0b), 1);
+    }
 }
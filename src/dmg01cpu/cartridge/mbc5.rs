@@ -1,26 +1,37 @@
-use super::Log;
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Features, Mbc};
 
 pub struct MBC5 {
     log_mode: u8,
+    has_rumble: bool,
     rom_bank_low: u8,
     rom_bank_high: u8,
     ram_bank: u8,
     enable_ram: u8,
+    rumble: bool,        // motor on/off
+    rumble_cycles: u32,  // cycles with the motor on, games drive it with pwm
+    elapsed_cycles: u32, // since the last get_rumble
+    dirty: bool,
 }
 
 impl MBC5 {
-    pub fn new(log_mode: u8) -> Self {
+    pub fn new(log_mode: u8, features: Features) -> Self {
         MBC5 {
             log_mode,
+            has_rumble: features.rumble,
             rom_bank_low: 0x00,
             rom_bank_high: 0x00,
             ram_bank: 0x00,
             enable_ram: 0x00,
+            rumble: false,
+            rumble_cycles: 0,
+            elapsed_cycles: 0,
+            dirty: false,
         }
     }
 
     fn get_rom_bank(&self) -> u16 {
-        (self.rom_bank_high as u16) << 8 | self.rom_bank_low as u16
+        // 9bits:512 banks
+        ((self.rom_bank_high as u16) << 8 | self.rom_bank_low as u16) & 0x01ff
     }
 
     fn is_ram_enabled(&self) -> bool {
@@ -30,26 +41,29 @@ impl MBC5 {
             false
         }
     }
+}
 
-    pub fn write(&mut self, address: u16, value: u8, ram: &mut Vec<u8>) {
+impl Mbc for MBC5 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
         match address {
             0x0000..=0x1fff => self.enable_ram = value,
             0x2000..=0x2fff => self.rom_bank_low = value,
             0x3000..=0x3fff => self.rom_bank_high = value & 0x01,
-            0x4000..=0x5fff => self.ram_bank = value & 0x0f,
+            0x4000..=0x5fff => {
+                if self.has_rumble {
+                    // 0x08:motor
+                    self.rumble = value & 0x08 == 0x08;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0f;
+                }
+            }
             0xa000..=0xbfff => {
                 if self.is_ram_enabled() {
-                    let offset: usize = (8 * 1024) * self.ram_bank as usize;
-                    Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                    let ram_address = (address & 0x1fff) as usize + offset;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
-                        self.log_mode,
-                    );
-                    ram[ram_address] = value;
-                } else {
-                    panic!("unexpected address {:#08x}.need return?", address)
+                    self.dirty |=
+                        write_ram_bank(ram, self.ram_bank as usize, address, value, self.log_mode);
                 }
+                // disabled:ignore
             }
             _ => {
                 panic!("write address error:{:#08x}", address)
@@ -57,29 +71,16 @@ impl MBC5 {
         }
     }
 
-    pub fn read(&self, address: u16, rom: &Vec<u8>, ram: &Vec<u8>) -> u8 {
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
         match address {
-            0x0000..=0x3fff => rom[address as usize],
-            0x4000..=0x7fff => {
-                let offset: usize = (16 * 1024) * self.get_rom_bank() as usize;
-                rom[(address & 0x3fff) as usize + offset]
-            }
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank() as usize, address),
 
             0xa000..=0xbfff => {
                 if self.is_ram_enabled() {
-                    let offset: usize = (8 * 1024) * self.ram_bank as usize;
-                    Log::rom(format!("{: <15}:{:#04x}", "offset", offset), self.log_mode);
-                    let ram_address = (address & 0x1fff) as usize + offset;
-                    Log::rom(
-                        format!("{: <15}:{:#04x}", "ram address", ram_address),
-                        self.log_mode,
-                    );
-                    ram[ram_address]
+                    read_ram_bank(ram, self.ram_bank as usize, address, self.log_mode)
                 } else {
-                    panic!(
-                        "unexpected address {:#08x}.ram is disabled.need 0xff?",
-                        address
-                    )
+                    0xff // disabled:open bus
                 }
             }
 
@@ -88,4 +89,49 @@ impl MBC5 {
             }
         }
     }
+
+    fn tick(&mut self, cycles: u8) {
+        self.elapsed_cycles += cycles as u32;
+        if self.rumble {
+            self.rumble_cycles += cycles as u32;
+        }
+    }
+
+    fn get_rumble(&mut self) -> f32 {
+        let duty: f32 = match self.elapsed_cycles {
+            0 => (self.rumble as u8) as f32,
+            _ => self.rumble_cycles as f32 / self.elapsed_cycles as f32,
+        };
+        self.rumble_cycles = 0;
+        self.elapsed_cycles = 0;
+        duty
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rumble_is_the_duty_cycle_of_the_frame() {
+        let mut mbc5: MBC5 = MBC5::new(0, Features::new(0x1c)); // mbc5+rumble
+
+        // pwm:on 1 of 4 writes
+        for count in 0..400 {
+            let value: u8 = if count % 4 == 0 { 0x08 } else { 0x00 };
+            mbc5.write(0x4000, value, &mut []);
+            mbc5.tick(4);
+        }
+        assert_eq!(mbc5.get_rumble(), 0.25);
+
+        // the count is reset, the motor state is kept
+        mbc5.write(0x4000, 0x08, &mut []);
+        assert_eq!(mbc5.get_rumble(), 1.0);
+        mbc5.tick(4);
+        assert_eq!(mbc5.get_rumble(), 1.0);
+    }
 }
@@ -0,0 +1,213 @@
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Log, Mbc};
+
+const FLASH_SIZE: usize = 0x100000; // 1MB (MX29F008)
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+const WINDOW_SIZE: usize = 0x2000; // 8KB
+
+/* MBC6 with two 8KB rom/flash windows and two 4KB ram windows */
+pub struct MBC6 {
+    log_mode: u8,
+    enable_ram: u8,
+    ram_bank_a: u8,
+    ram_bank_b: u8,
+    enable_flash: bool,
+    enable_flash_write: bool,
+    rom_bank_a: u8,
+    rom_bank_b: u8,
+    is_flash_a: bool, // 0x08:flash, 0x00:rom
+    is_flash_b: bool,
+    /* Flash */
+    flash: Vec<u8>,
+    flash_step: u8, // unlock sequence 0xaa -> 0x55
+    flash_mode: u8, // 0x00:read, 0x80:erase, 0x90:id, 0xa0:program
+    dirty: bool,
+}
+
+impl MBC6 {
+    pub fn new(log_mode: u8) -> Self {
+        MBC6 {
+            log_mode,
+            enable_ram: 0x00,
+            ram_bank_a: 0x00,
+            ram_bank_b: 0x00,
+            enable_flash: false,
+            enable_flash_write: false,
+            rom_bank_a: 0x00,
+            rom_bank_b: 0x00,
+            is_flash_a: false,
+            is_flash_b: false,
+            flash: vec![0xff; FLASH_SIZE], // erased
+            flash_step: 0,
+            flash_mode: 0x00,
+            dirty: false,
+        }
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.enable_ram & 0x0f == 0x0a
+    }
+
+    /// 4KB ram bank as (8KB bank, address)
+    fn get_ram_bank(&self, address: u16) -> (usize, u16) {
+        let bank: u8 = match address {
+            0xa000..=0xafff => self.ram_bank_a,
+            _ => self.ram_bank_b, // 0xb000-0xbfff
+        };
+        (
+            (bank >> 1) as usize,
+            ((bank as u16 & 0x01) << 12) | (address & 0x0fff),
+        )
+    }
+
+    /// (bank, is flash) of the window
+    fn get_window(&self, address: u16) -> (u8, bool) {
+        match address {
+            0x4000..=0x5fff => (self.rom_bank_a, self.is_flash_a),
+            _ => (self.rom_bank_b, self.is_flash_b), // 0x6000-0x7fff
+        }
+    }
+
+    fn get_flash_address(bank: u8, address: u16) -> usize {
+        ((bank as usize & 0x7f) * WINDOW_SIZE + (address as usize & 0x1fff)) % FLASH_SIZE
+    }
+
+    fn read_flash(&self, flash_address: usize) -> u8 {
+        match self.flash_mode {
+            // manufacturer and device id
+            0x90 => match flash_address & 0xff {
+                0x00 => 0xc2,
+                0x01 => 0x81,
+                _ => self.flash[flash_address],
+            },
+            _ => self.flash[flash_address],
+        }
+    }
+
+    /// jedec command sequence:0x5555=0xaa, 0x2aaa=0x55, command
+    fn write_flash(&mut self, flash_address: usize, value: u8) {
+        if self.flash_mode == 0xa0 {
+            // program:bits can only be cleared
+            self.flash[flash_address] &= value;
+            self.flash_mode = 0x00;
+            self.dirty = true;
+            return;
+        }
+        if value == 0xf0 {
+            // reset
+            self.flash_mode = 0x00;
+            self.flash_step = 0;
+            return;
+        }
+
+        let command_address: usize = flash_address & 0x7fff;
+        self.flash_step = match (self.flash_step, command_address, value) {
+            (0, 0x5555, 0xaa) => 1,
+            (1, 0x2aaa, 0x55) => 2,
+            (2, _, _) => {
+                match (self.flash_mode, command_address, value) {
+                    (0x80, 0x5555, 0x10) => {
+                        // chip erase
+                        self.flash.fill(0xff);
+                        self.flash_mode = 0x00;
+                        self.dirty = true;
+                    }
+                    (0x80, _, 0x30) => {
+                        // sector erase (128KB)
+                        let start: usize = flash_address & !(FLASH_SECTOR_SIZE - 1);
+                        self.flash[start..start + FLASH_SECTOR_SIZE].fill(0xff);
+                        self.flash_mode = 0x00;
+                        self.dirty = true;
+                    }
+                    (_, 0x5555, 0x80 | 0x90 | 0xa0) => self.flash_mode = value,
+                    _ => self.flash_mode = 0x00, // unknown:ignore
+                }
+                Log::rom(
+                    format!(
+                        "{: <15}:{:#04x} {:#08x}",
+                        "flash command", value, flash_address
+                    ),
+                    self.log_mode,
+                );
+                0
+            }
+            _ => 0,
+        };
+    }
+}
+
+impl Mbc for MBC6 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x03ff => self.enable_ram = value,
+            0x0400..=0x07ff => self.ram_bank_a = value & 0x07,
+            0x0800..=0x0bff => self.ram_bank_b = value & 0x07,
+            0x0c00..=0x0fff => self.enable_flash = value & 0x01 == 0x01,
+            0x1000 => self.enable_flash_write = value & 0x01 == 0x01,
+            0x1001..=0x1fff => (), // no register
+            0x2000..=0x27ff => self.rom_bank_a = value & 0x7f,
+            0x2800..=0x2fff => self.is_flash_a = value & 0x08 == 0x08,
+            0x3000..=0x37ff => self.rom_bank_b = value & 0x7f,
+            0x3800..=0x3fff => self.is_flash_b = value & 0x08 == 0x08,
+            0x4000..=0x7fff => {
+                let (bank, is_flash) = self.get_window(address);
+                if is_flash && self.enable_flash && self.enable_flash_write {
+                    self.write_flash(Self::get_flash_address(bank, address), value);
+                }
+                // rom:ignore
+            }
+            0xa000..=0xbfff => {
+                if self.is_ram_enabled() {
+                    let (bank, address) = self.get_ram_bank(address);
+                    self.dirty |= write_ram_bank(ram, bank, address, value, self.log_mode);
+                }
+                // disabled:ignore
+            }
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => {
+                let (bank, is_flash) = self.get_window(address);
+                if is_flash {
+                    self.read_flash(Self::get_flash_address(bank, address))
+                } else {
+                    // 8KB bank
+                    let offset: usize = bank as usize * WINDOW_SIZE + (address as usize & 0x1fff);
+                    rom[offset % rom.len()]
+                }
+            }
+
+            0xa000..=0xbfff => {
+                if self.is_ram_enabled() {
+                    let (bank, address) = self.get_ram_bank(address);
+                    read_ram_bank(ram, bank, address, self.log_mode)
+                } else {
+                    0xff // disabled:open bus
+                }
+            }
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        self.flash.clone()
+    }
+
+    fn deserialize(&mut self, data: &[u8]) {
+        if data.len() == FLASH_SIZE {
+            self.flash.copy_from_slice(data);
+        }
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
@@ -0,0 +1,250 @@
+use super::{read_rom_bank, Log, Mbc};
+
+const ACCELEROMETER_CENTER: u16 = 0x81d0;
+const ACCELEROMETER_GRAVITY: f32 = 112.0; // 0x70:1g
+
+/* MBC7 with accelerometer and 93LC56 eeprom (128x16bit, stored in the ram) */
+pub struct MBC7 {
+    log_mode: u8,
+    rom_bank: u8,
+    enable_ram1: u8,
+    enable_ram2: u8,
+    /* Accelerometer */
+    tilt_x: f32, // -1.0(left) to 1.0(right)
+    tilt_y: f32, // -1.0(up) to 1.0(down)
+    latch_x: u16,
+    latch_y: u16,
+    is_latch_erased: bool,
+    /* EEPROM */
+    eeprom_cs: bool,
+    eeprom_clk: bool,
+    eeprom_di: bool,
+    eeprom_do: bool,
+    eeprom_command: u32, // shift register
+    eeprom_bits: u8,     // received bits after the start bit
+    eeprom_read: u16,    // data to send
+    eeprom_read_bits: u8,
+    eeprom_write_enable: bool,
+    dirty: bool,
+}
+
+impl MBC7 {
+    pub fn new(log_mode: u8) -> Self {
+        MBC7 {
+            log_mode,
+            rom_bank: 0x01,
+            enable_ram1: 0x00,
+            enable_ram2: 0x00,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            latch_x: 0x8000,
+            latch_y: 0x8000,
+            is_latch_erased: false,
+            eeprom_cs: false,
+            eeprom_clk: false,
+            eeprom_di: false,
+            eeprom_do: true,
+            eeprom_command: 0,
+            eeprom_bits: 0,
+            eeprom_read: 0,
+            eeprom_read_bits: 0,
+            eeprom_write_enable: false,
+            dirty: false,
+        }
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.enable_ram1 & 0x0f == 0x0a && self.enable_ram2 == 0x40
+    }
+
+    fn latch_accelerometer(&mut self) {
+        self.latch_x = (ACCELEROMETER_CENTER as f32 + self.tilt_x * ACCELEROMETER_GRAVITY) as u16;
+        self.latch_y = (ACCELEROMETER_CENTER as f32 + self.tilt_y * ACCELEROMETER_GRAVITY) as u16;
+    }
+
+    fn read_word(ram: &[u8], address: u8) -> u16 {
+        let index: usize = (address as usize & 0x7f) << 1;
+        u16::from_le_bytes([ram[index], ram[index + 1]])
+    }
+
+    fn write_word(&mut self, ram: &mut [u8], address: u8, value: u16) {
+        if self.eeprom_write_enable && Self::read_word(ram, address) != value {
+            let index: usize = (address as usize & 0x7f) << 1;
+            ram[index..index + 2].copy_from_slice(&value.to_le_bytes());
+            self.dirty = true;
+        }
+    }
+
+    fn reset_eeprom_command(&mut self) {
+        self.eeprom_command = 0;
+        self.eeprom_bits = 0;
+    }
+
+    /// command:start bit, opecode(2bits), address(8bits) and data(16bits)
+    fn clock_eeprom(&mut self, ram: &mut [u8]) {
+        if self.eeprom_read_bits > 0 {
+            // msb first
+            self.eeprom_do = self.eeprom_read & 0x8000 == 0x8000;
+            self.eeprom_read <<= 1;
+            self.eeprom_read_bits -= 1;
+            return;
+        }
+
+        if self.eeprom_bits == 0 && !self.eeprom_di {
+            // waiting for the start bit
+            return;
+        }
+        self.eeprom_command = self.eeprom_command << 1 | self.eeprom_di as u32;
+        self.eeprom_bits += 1;
+
+        // 1 + 2 + 8 bits
+        if self.eeprom_bits < 11 {
+            return;
+        }
+        let opecode: u8 = ((self.eeprom_command >> (self.eeprom_bits - 3)) & 0x03) as u8;
+        let address: u8 = (self.eeprom_command >> (self.eeprom_bits - 11)) as u8;
+        let data: u16 = self.eeprom_command as u16;
+
+        match (opecode, address >> 6) {
+            (0x02, _) => {
+                // read:dummy 0 and 16bits
+                self.eeprom_read = Self::read_word(ram, address);
+                self.eeprom_read_bits = 16;
+                self.eeprom_do = false;
+                self.reset_eeprom_command();
+            }
+            (0x01, _) | (0x00, 0x01) if self.eeprom_bits < 27 => (), // waiting for data
+            (0x01, _) => {
+                // write
+                self.write_word(ram, address, data);
+                self.eeprom_do = true; // ready
+                self.reset_eeprom_command();
+            }
+            (0x03, _) => {
+                // erase
+                self.write_word(ram, address, 0xffff);
+                self.eeprom_do = true;
+                self.reset_eeprom_command();
+            }
+            (0x00, 0x00) => {
+                // ewds:write disable
+                self.eeprom_write_enable = false;
+                self.reset_eeprom_command();
+            }
+            (0x00, 0x01) => {
+                // wral:write all
+                for i in 0..0x80 {
+                    self.write_word(ram, i, data);
+                }
+                self.eeprom_do = true;
+                self.reset_eeprom_command();
+            }
+            (0x00, 0x02) => {
+                // eral:erase all
+                for i in 0..0x80 {
+                    self.write_word(ram, i, 0xffff);
+                }
+                self.eeprom_do = true;
+                self.reset_eeprom_command();
+            }
+            _ => {
+                // ewen:write enable
+                self.eeprom_write_enable = true;
+                self.reset_eeprom_command();
+            }
+        }
+    }
+
+    fn write_eeprom(&mut self, value: u8, ram: &mut [u8]) {
+        // 0x80:cs, 0x40:clk, 0x02:di
+        let cs: bool = value & 0x80 == 0x80;
+        let clk: bool = value & 0x40 == 0x40;
+        self.eeprom_di = value & 0x02 == 0x02;
+
+        if !cs {
+            self.reset_eeprom_command();
+            self.eeprom_read_bits = 0;
+        } else if self.eeprom_cs && !self.eeprom_clk && clk {
+            // rising edge
+            self.clock_eeprom(ram);
+        }
+
+        self.eeprom_cs = cs;
+        self.eeprom_clk = clk;
+    }
+
+    fn read_eeprom(&self) -> u8 {
+        (self.eeprom_cs as u8) << 7
+            | (self.eeprom_clk as u8) << 6
+            | (self.eeprom_di as u8) << 1
+            | self.eeprom_do as u8
+    }
+}
+
+impl Mbc for MBC7 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => self.enable_ram1 = value,
+            0x2000..=0x3fff => self.rom_bank = value & 0x7f,
+            0x4000..=0x5fff => self.enable_ram2 = value,
+            0x6000..=0x7fff => (), // no register
+            0xa000..=0xafff if self.is_ram_enabled() => match address & 0x00f0 {
+                0x0000 if value == 0x55 => {
+                    // erase the latched values
+                    self.latch_x = 0x8000;
+                    self.latch_y = 0x8000;
+                    self.is_latch_erased = true;
+                }
+                0x0010 if value == 0xaa && self.is_latch_erased => {
+                    self.latch_accelerometer();
+                    self.is_latch_erased = false;
+                }
+                0x0080 => self.write_eeprom(value, ram),
+                _ => (), // read only
+            },
+            0xa000..=0xbfff => (), // disabled or unmapped:ignore
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], _ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.rom_bank as usize, address),
+
+            0xa000..=0xafff if self.is_ram_enabled() => match address & 0x00f0 {
+                0x0020 => self.latch_x as u8,
+                0x0030 => (self.latch_x >> 8) as u8,
+                0x0040 => self.latch_y as u8,
+                0x0050 => (self.latch_y >> 8) as u8,
+                0x0060 => 0x00,
+                0x0080 => self.read_eeprom(),
+                _ => 0xff,
+            },
+            0xa000..=0xbfff => 0xff, // disabled or unmapped:open bus
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        let (x, y) = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+        if x == self.tilt_x && y == self.tilt_y {
+            return;
+        }
+        self.tilt_x = x;
+        self.tilt_y = y;
+        Log::rom(
+            format!("{: <15}:{:.2},{:.2}", "tilt", self.tilt_x, self.tilt_y),
+            self.log_mode,
+        );
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
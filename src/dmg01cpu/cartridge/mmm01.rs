@@ -0,0 +1,137 @@
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Mbc};
+
+/* MMM01 multicart:boots the menu in the last 32KB, then locks the selected game */
+pub struct MMM01 {
+    log_mode: u8,
+    is_locked: bool, // mapped
+    enable_ram: u8,
+    bank_mode: u8,
+    is_mode_locked: bool,
+    rom_bank_low: u8,  // bank bit 0-4
+    rom_bank_high: u8, // bank bit 5-8 (outer)
+    rom_bank_mask: u8, // bank bit 1-4 fixed by the menu
+    ram_bank_low: u8,  // bank bit 0-1
+    ram_bank_high: u8, // bank bit 2-3 (outer)
+    dirty: bool,
+}
+
+impl MMM01 {
+    pub fn new(log_mode: u8) -> Self {
+        MMM01 {
+            log_mode,
+            is_locked: false,
+            enable_ram: 0x00,
+            bank_mode: 0x00,
+            is_mode_locked: false,
+            rom_bank_low: 0x00,
+            rom_bank_high: 0x00,
+            rom_bank_mask: 0x00,
+            ram_bank_low: 0x00,
+            ram_bank_high: 0x00,
+            dirty: false,
+        }
+    }
+
+    fn get_rom_bank0(&self) -> usize {
+        if !self.is_locked {
+            return 0x1fe; // unmapped:last 32KB
+        }
+        (self.rom_bank_high as usize) << 5 | (self.rom_bank_low & self.rom_bank_mask) as usize
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        if !self.is_locked {
+            return 0x1ff; // unmapped:last 32KB
+        }
+
+        let mut bank_low: u8 = self.rom_bank_low;
+        if bank_low & !self.rom_bank_mask & 0x1f == 0x00 {
+            bank_low |= 0x01; // specification
+        }
+        (self.rom_bank_high as usize) << 5 | bank_low as usize
+    }
+
+    fn get_ram_bank(&self) -> usize {
+        let bank_low: u8 = match self.bank_mode {
+            0x01 => self.ram_bank_low, // ram banking mode
+            _ => 0x00,                 // 0x00:rom banking mode
+        };
+        (self.ram_bank_high << 2 | bank_low) as usize
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.enable_ram & 0x0f == 0x0a
+    }
+}
+
+impl Mbc for MMM01 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => {
+                self.enable_ram = value;
+                if !self.is_locked {
+                    self.is_locked = value & 0x40 == 0x40; // 0x40:map the selected game
+                }
+            }
+            0x2000..=0x3fff => {
+                if self.is_locked {
+                    // masked bits are fixed by the menu
+                    self.rom_bank_low = (self.rom_bank_low & self.rom_bank_mask)
+                        | (value & 0x1f & !self.rom_bank_mask);
+                } else {
+                    self.rom_bank_low = value & 0x1f;
+                    self.rom_bank_high = (self.rom_bank_high & 0x0c) | (value >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5fff => {
+                self.ram_bank_low = value & 0x03;
+                if !self.is_locked {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (self.rom_bank_high & 0x03) | (value >> 2) & 0x0c;
+                    self.is_mode_locked = value & 0x40 == 0x40;
+                }
+            }
+            0x6000..=0x7fff => {
+                if !(self.is_locked && self.is_mode_locked) {
+                    self.bank_mode = value & 0x01;
+                }
+                if !self.is_locked {
+                    self.rom_bank_mask = (value >> 1) & 0x1e;
+                }
+            }
+            0xa000..=0xbfff => {
+                if self.is_ram_enabled() {
+                    self.dirty |=
+                        write_ram_bank(ram, self.get_ram_bank(), address, value, self.log_mode);
+                }
+                // disabled:ignore
+            }
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, self.get_rom_bank0(), address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank(), address),
+
+            0xa000..=0xbfff => {
+                if self.is_ram_enabled() {
+                    read_ram_bank(ram, self.get_ram_bank(), address, self.log_mode)
+                } else {
+                    0xff // disabled:open bus
+                }
+            }
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
@@ -0,0 +1,247 @@
+use super::{read_ram_bank, read_rom_bank, write_ram_bank, Log, Mbc};
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 112;
+const REGISTER_SIZE: usize = 0x36;
+const DITHER_START: usize = 0x06; // 4x4 matrix, 3 thresholds each
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/* Pocket Camera (MAC-GBD) with a M64282FP sensor */
+pub struct PocketCamera {
+    log_mode: u8,
+    enable_ram: u8,
+    rom_bank: u8,
+    ram_bank: u8, // 0x10:camera registers
+    registers: [u8; REGISTER_SIZE],
+    busy: u32, // cpu cycles until the capture ends
+    image: Vec<u8>,
+    dirty: bool,
+}
+
+impl PocketCamera {
+    pub fn new(log_mode: u8) -> Self {
+        PocketCamera {
+            log_mode,
+            enable_ram: 0x00,
+            rom_bank: 0x01,
+            ram_bank: 0x00,
+            registers: [0; REGISTER_SIZE],
+            busy: 0,
+            image: Self::get_test_pattern(),
+            dirty: false,
+        }
+    }
+
+    /// diagonal gradient with a checker board
+    fn get_test_pattern() -> Vec<u8> {
+        let mut image: Vec<u8> = vec![0; WIDTH * HEIGHT];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let gradient: usize = (x + y) * 0xff / (WIDTH + HEIGHT - 2);
+                let checker: bool = (x / 16 + y / 16) % 2 == 0;
+                image[y * WIDTH + x] = match checker {
+                    true => gradient as u8,
+                    _ => 0xff - gradient as u8,
+                };
+            }
+        }
+        image
+    }
+
+    /// binary pgm (P5), scaled to the sensor size
+    fn load_pgm(data: &[u8]) -> Option<Vec<u8>> {
+        // header:magic, width, height, maxval (comments start with '#')
+        let mut fields: Vec<usize> = Vec::new();
+        let mut index: usize = 2;
+        if data.get(0..2)? != b"P5" {
+            return None;
+        }
+        while fields.len() < 3 {
+            match *data.get(index)? {
+                b'#' => {
+                    while *data.get(index)? != b'\n' {
+                        index += 1;
+                    }
+                }
+                digit @ b'0'..=b'9' => {
+                    let mut value: usize = (digit - b'0') as usize;
+                    index += 1;
+                    while let digit @ b'0'..=b'9' = *data.get(index)? {
+                        value = value * 10 + (digit - b'0') as usize;
+                        index += 1;
+                    }
+                    fields.push(value);
+                    continue;
+                }
+                byte if byte.is_ascii_whitespace() => (),
+                _ => return None,
+            }
+            index += 1;
+        }
+        index += 1; // single whitespace
+
+        let (width, height, maxval) = (fields[0], fields[1], fields[2]);
+        if width == 0 || height == 0 || maxval == 0 || maxval > 0xff {
+            return None;
+        }
+        let pixels: &[u8] = data.get(index..index + width * height)?;
+
+        let mut image: Vec<u8> = vec![0; WIDTH * HEIGHT];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let pixel: usize =
+                    pixels[(y * height / HEIGHT) * width + x * width / WIDTH] as usize;
+                image[y * WIDTH + x] = (pixel.min(maxval) * 0xff / maxval) as u8;
+            }
+        }
+        Some(image)
+    }
+
+    fn get_exposure(&self) -> u32 {
+        (self.registers[0x02] as u32) << 8 | self.registers[0x03] as u32
+    }
+
+    /// sensor value with the exposure and invert applied
+    fn get_sensor_color(&self, x: usize, y: usize) -> f32 {
+        let x: usize = x.min(WIDTH - 1);
+        let y: usize = y.min(HEIGHT - 1);
+        let mut color: u8 = self.image[y * WIDTH + x];
+        if self.registers[0x04] & 0x08 == 0x08 {
+            color = 0xff - color; // invert
+        }
+        (color as u32 * self.get_exposure() / 0x1000).min(0xff) as f32
+    }
+
+    /// exposure, edge enhancement and dithering to 2bpp tiles at 0x0100 of ram bank 0
+    fn capture(&mut self, ram: &mut [u8]) {
+        // 0xe0:2d edge enhancement mode
+        let is_edge: bool = self.registers[0x01] & 0xe0 == 0xe0;
+        let ratio: f32 = EDGE_RATIOS[(self.registers[0x04] >> 4) as usize & 0x07];
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let mut color: f32 = self.get_sensor_color(x, y);
+                if is_edge {
+                    let neighbors: f32 = self.get_sensor_color(x.saturating_sub(1), y)
+                        + self.get_sensor_color(x + 1, y)
+                        + self.get_sensor_color(x, y.saturating_sub(1))
+                        + self.get_sensor_color(x, y + 1);
+                    color += (color * 4.0 - neighbors) * ratio;
+                }
+                let color: u8 = color.clamp(0.0, 255.0) as u8;
+
+                let dither: usize = DITHER_START + ((x & 3) + (y & 3) * 4) * 3;
+                let shade: u8 = match color {
+                    _ if color < self.registers[dither] => 0x03,
+                    _ if color < self.registers[dither + 1] => 0x02,
+                    _ if color < self.registers[dither + 2] => 0x01,
+                    _ => 0x00,
+                };
+
+                let tile: usize = (y / 8) * (WIDTH / 8) + x / 8;
+                let address: u16 = (0x0100 + tile * 16 + (y & 7) * 2) as u16;
+                let bit: u8 = 0x80 >> (x & 7);
+                for (plane, mask) in [(0, 0x01), (1, 0x02)] {
+                    let value: u8 = read_ram_bank(ram, 0x00, address + plane, self.log_mode);
+                    let value: u8 = match shade & mask {
+                        0x00 => value & !bit,
+                        _ => value | bit,
+                    };
+                    self.dirty |= write_ram_bank(ram, 0x00, address + plane, value, self.log_mode);
+                }
+            }
+        }
+    }
+
+    /// m-cycles (1MHz) to t-cycles
+    fn get_capture_cycles(&self) -> u32 {
+        let n: u32 = match self.registers[0x01] & 0x80 {
+            0x00 => 512,
+            _ => 0,
+        };
+        (32446 + n + 16 * self.get_exposure()) * 4
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => self.enable_ram = value,
+            0x2000..=0x3fff => self.rom_bank = value & 0x3f,
+            0x4000..=0x5fff => self.ram_bank = value & 0x1f,
+            0x6000..=0x7fff => (), // no register
+            0xa000..=0xbfff if self.ram_bank & 0x10 == 0x10 => {
+                // registers are mirrored every 0x80 bytes
+                match (address & 0x7f) as usize {
+                    0x00 => {
+                        self.registers[0x00] = value & 0x07;
+                        if value & 0x01 == 0x01 && self.busy == 0 {
+                            Log::rom(
+                                format!("{: <15}:{:#06x}", "capture", self.get_exposure()),
+                                self.log_mode,
+                            );
+                            self.capture(ram);
+                            self.busy = self.get_capture_cycles();
+                        }
+                    }
+                    register @ 0x01..=0x35 => self.registers[register] = value,
+                    _ => (), // unmapped:ignore
+                }
+            }
+            0xa000..=0xbfff => {
+                // ram is locked during capture
+                if self.enable_ram & 0x0f == 0x0a && self.busy == 0 {
+                    self.dirty |=
+                        write_ram_bank(ram, self.ram_bank as usize, address, value, self.log_mode);
+                }
+                // disabled:ignore
+            }
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.rom_bank as usize, address),
+
+            0xa000..=0xbfff if self.ram_bank & 0x10 == 0x10 => match address & 0x7f {
+                // only the capture status is readable
+                0x00 => (self.registers[0x00] & 0x06) | (self.busy > 0) as u8,
+                _ => 0x00,
+            },
+            0xa000..=0xbfff => {
+                // readable without enabling
+                if self.busy == 0 {
+                    read_ram_bank(ram, self.ram_bank as usize, address, self.log_mode)
+                } else {
+                    0x00 // capturing
+                }
+            }
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.busy = self.busy.saturating_sub(cycles as u32);
+    }
+
+    fn set_camera_image(&mut self, data: &[u8]) {
+        match Self::load_pgm(data) {
+            Some(image) => self.image = image,
+            None => Log::info(
+                format!("{: <5}:unsupported image, test pattern", "Cam"),
+                self.log_mode,
+            ),
+        }
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
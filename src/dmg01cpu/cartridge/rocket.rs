@@ -0,0 +1,51 @@
+use super::{read_rom_bank, Mbc, NINTENDO_LOGO};
+
+/* Rocket Games:own logo in the header, a lockout circuit shows the official one to the boot rom */
+pub struct Rocket {
+    rom_bank: u8,
+}
+
+impl Rocket {
+    pub fn new(_log_mode: u8) -> Self {
+        Rocket { rom_bank: 0x01 }
+    }
+
+    /// unofficial logo and the publisher name in bank 0
+    pub fn detect(rom: &[u8]) -> bool {
+        const NAME: &[u8] = b"ROCKET";
+        let bank0: &[u8] = &rom[..std::cmp::min(rom.len(), 0x4000)];
+        rom.len() > 0x8000
+            && rom[0x0104..0x0134] != NINTENDO_LOGO
+            && bank0.windows(NAME.len()).any(|window| window == NAME)
+    }
+}
+
+impl Mbc for Rocket {
+    fn write(&mut self, address: u16, value: u8, _ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => (), // no register
+            0x2000..=0x3fff => {
+                self.rom_bank = match value {
+                    0x00 => 0x01, // specification
+                    _ => value,
+                }
+            }
+            0x4000..=0x7fff => (), // no register
+            0xa000..=0xbfff => (), // no ram
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], _ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.rom_bank as usize, address),
+            0xa000..=0xbfff => 0xff, // no ram
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::{read_rom_bank, Mbc, NINTENDO_LOGO};
+
+/* Sachen MMC1/MMC2 with a scrambled header */
+// the boot rom reads the official logo while locked, the mapper is unlocked before 0x0100
+// there is no boot rom here, the mapper starts unlocked
+pub struct Sachen {
+    rom_bank: u8,
+    base_bank: u8, // outer bank
+    bank_mask: u8, // bits taken from the base bank
+}
+
+impl Sachen {
+    pub fn new(_log_mode: u8) -> Self {
+        Sachen {
+            rom_bank: 0x01,
+            base_bank: 0x00,
+            bank_mask: 0x00,
+        }
+    }
+
+    /// locked reads of 0x0100-0x01ff:a7 is forced high, a0<->a6 and a1<->a4
+    fn get_locked_address(address: u16) -> u16 {
+        let address: u16 = address | 0x0080;
+        (address & 0xffac)
+            | (address & 0x40) >> 6
+            | (address & 0x10) >> 3
+            | (address & 0x02) << 3
+            | (address & 0x01) << 6
+    }
+
+    /// the official logo is only seen while locked
+    fn has_hidden_logo(rom: &[u8]) -> bool {
+        rom.len() >= 0x8000
+            && rom[0x0104..0x0134] != NINTENDO_LOGO
+            && (0..NINTENDO_LOGO.len()).all(|index| {
+                let address: u16 = Self::get_locked_address(0x0104 + index as u16);
+                rom[address as usize] == NINTENDO_LOGO[index]
+            })
+    }
+
+    fn is_cgb(rom: &[u8]) -> bool {
+        rom[Self::get_locked_address(0x0143) as usize] & 0x80 == 0x80
+    }
+
+    pub fn detect_mmc1(rom: &[u8]) -> bool {
+        Self::has_hidden_logo(rom) && !Self::is_cgb(rom)
+    }
+
+    /// mmc2 has an extra lock for the cgb boot rom
+    pub fn detect_mmc2(rom: &[u8]) -> bool {
+        Self::has_hidden_logo(rom) && Self::is_cgb(rom)
+    }
+
+    fn get_rom_bank0(&self) -> usize {
+        (self.base_bank & self.bank_mask) as usize
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        ((self.rom_bank & !self.bank_mask) | (self.base_bank & self.bank_mask)) as usize
+    }
+
+    /// the base and mask are writable while the rom bank is 0x30-0x3f
+    fn is_base_writable(&self) -> bool {
+        self.rom_bank & 0x30 == 0x30
+    }
+}
+
+impl Mbc for Sachen {
+    fn write(&mut self, address: u16, value: u8, _ram: &mut [u8]) {
+        match address {
+            0x0000..=0x1fff => {
+                if self.is_base_writable() {
+                    self.base_bank = value;
+                }
+            }
+            0x2000..=0x3fff => {
+                self.rom_bank = match value {
+                    0x00 => 0x01, // specification
+                    _ => value,
+                }
+            }
+            0x4000..=0x5fff => {
+                if self.is_base_writable() {
+                    self.bank_mask = value;
+                }
+            }
+            0x6000..=0x7fff => (), // mmc2 lock:unlocked
+            0xa000..=0xbfff => (), // no ram
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], _ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, self.get_rom_bank0(), address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank(), address),
+            0xa000..=0xbfff => 0xff, // no ram
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn get_header_address(&self, address: u16) -> u16 {
+        Self::get_locked_address(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY_POINT: [u8; 4] = [0x00, 0xc3, 0x50, 0x01]; // nop, jp 0x0150
+
+    /// the own logo at 0x0104, the official logo at the locked addresses
+    fn new_rom(cgb_flag: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = vec![0; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&ENTRY_POINT);
+        rom[0x0104..0x0134].fill(0x5a);
+        for (index, value) in NINTENDO_LOGO.iter().enumerate() {
+            rom[Sachen::get_locked_address(0x0104 + index as u16) as usize] = *value;
+        }
+        rom[Sachen::get_locked_address(0x0143) as usize] = cgb_flag;
+        rom
+    }
+
+    #[test]
+    fn entry_point_is_not_scrambled() {
+        let rom: Vec<u8> = new_rom(0x00);
+        assert!(Sachen::detect_mmc1(&rom));
+        assert!(!Sachen::detect_mmc2(&rom));
+
+        let sachen: Sachen = Sachen::new(0);
+        let entry_point: Vec<u8> = (0x0100..0x0104)
+            .map(|address| sachen.read(address, &rom, &[]))
+            .collect();
+        assert_eq!(entry_point, ENTRY_POINT);
+    }
+
+    #[test]
+    fn cgb_flag_is_read_locked() {
+        let rom: Vec<u8> = new_rom(0x80);
+        assert!(Sachen::detect_mmc2(&rom));
+        assert!(!Sachen::detect_mmc1(&rom));
+
+        let address: u16 = Sachen::new(0).get_header_address(0x0143);
+        assert_eq!(rom[address as usize], 0x80);
+        assert_eq!(rom[0x0143], 0x00);
+    }
+}
@@ -0,0 +1,289 @@
+use super::{
+    get_current_time_sec, get_elapsed_time_sec, read_ram_bank, read_rom_bank, write_ram_bank,
+    Common, Log, Mbc,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const RTC_FOOTER_SIZE: usize = 16;
+
+/* TAMA5 (Tamagotchi 3) with 32 bytes eeprom and rtc, accessed by 4bit registers */
+pub struct TAMA5 {
+    log_mode: u8,
+    register: u8, // selected by a001
+    // 0x00-0x01:rom bank, 0x04-0x05:write data, 0x06:command and address bit 4, 0x07:address
+    registers: [u8; 8],
+    counter: u32, // cpu 4194304 Hz, sub-second
+    /* RTC */
+    enable_timer: bool,
+    sec: u8,
+    min: u8,
+    hour: u8,
+    week: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    dirty: bool,
+}
+
+impl TAMA5 {
+    pub fn new(log_mode: u8) -> Self {
+        TAMA5 {
+            log_mode,
+            register: 0x00,
+            registers: [0; 8],
+            counter: 0,
+            enable_timer: true,
+            sec: 0,
+            min: 0,
+            hour: 0,
+            week: 0,
+            day: 1,
+            month: 1,
+            year: 0,
+            dirty: false,
+        }
+    }
+
+    fn get_rom_bank(&self) -> usize {
+        ((self.registers[0x01] & 0x01) << 4 | self.registers[0x00]) as usize
+    }
+
+    fn get_address(&self) -> u16 {
+        ((self.registers[0x06] & 0x01) << 4 | self.registers[0x07]) as u16
+    }
+
+    fn get_data(&self) -> u8 {
+        self.registers[0x05] << 4 | self.registers[0x04]
+    }
+
+    fn get_days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year & 0x03 == 0 => 29, // leap year
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn add_rtc_seconds(&mut self, seconds: u64) {
+        if !self.enable_timer || seconds == 0 {
+            return;
+        }
+
+        let total: u64 =
+            self.hour as u64 * 60 * 60 + self.min as u64 * 60 + self.sec as u64 + seconds;
+        self.sec = (total % 60) as u8;
+        self.min = (total / 60 % 60) as u8;
+        self.hour = (total / (60 * 60) % 24) as u8;
+
+        for _ in 0..total / SECONDS_PER_DAY {
+            self.week = (self.week + 1) % 7;
+            self.day += 1;
+            if self.day > self.get_days_in_month() {
+                self.day = 1;
+                self.month += 1;
+                if self.month > 12 {
+                    self.month = 1;
+                    self.year = (self.year + 1) % 100;
+                }
+            }
+        }
+    }
+
+    fn to_bcd(value: u8) -> u8 {
+        ((value / 10) << 4) | (value % 10)
+    }
+
+    fn from_bcd(value: u8) -> u8 {
+        (value >> 4) * 10 + (value & 0x0f)
+    }
+
+    /// bcd digit of the rtc page
+    /// 0x00-0x01:sec, 0x02-0x03:min, 0x04-0x05:hour, 0x06:week, 0x07-0x08:day, 0x09-0x0a:month, 0x0b-0x0c:year
+    fn read_rtc_page(&self, index: u8) -> u8 {
+        let value: u8 = match index {
+            0x00..=0x01 => self.sec,
+            0x02..=0x03 => self.min,
+            0x04..=0x05 => self.hour,
+            0x06 => return self.week,
+            0x07..=0x08 => self.day,
+            0x09..=0x0a => self.month,
+            0x0b..=0x0c => self.year,
+            _ => return 0x00,
+        };
+        match index {
+            0x01 | 0x03 | 0x05 | 0x08 | 0x0a | 0x0c => value / 10,
+            _ => value % 10,
+        }
+    }
+
+    fn write_rtc_page(&mut self, index: u8, value: u8) {
+        let field: &mut u8 = match index {
+            0x00..=0x01 => &mut self.sec,
+            0x02..=0x03 => &mut self.min,
+            0x04..=0x05 => &mut self.hour,
+            0x06 => {
+                self.week = value % 7;
+                return;
+            }
+            0x07..=0x08 => &mut self.day,
+            0x09..=0x0a => &mut self.month,
+            0x0b..=0x0c => &mut self.year,
+            _ => return,
+        };
+        *field = match index {
+            0x01 | 0x03 | 0x05 | 0x08 | 0x0a | 0x0c => value * 10 + *field % 10,
+            _ => *field / 10 * 10 + value,
+        };
+    }
+
+    /// executed by writing the address (register 0x07)
+    fn execute_command(&mut self, ram: &mut [u8]) {
+        let address: u16 = self.get_address();
+        let data: u8 = self.get_data();
+        Log::rom(
+            format!(
+                "{: <15}:{:#04x} {:#04x} {:#04x}",
+                "tama5 command",
+                self.registers[0x06] >> 1,
+                address,
+                data
+            ),
+            self.log_mode,
+        );
+
+        match self.registers[0x06] >> 1 {
+            0x00 => self.dirty |= write_ram_bank(ram, 0x00, address, data, self.log_mode),
+            0x01 => (), // ram read:data is read from 0x0c-0x0d
+            0x02 => match address {
+                0x00 => self.enable_timer = false,
+                0x01 => self.enable_timer = true,
+                0x04 => {
+                    self.min = Self::from_bcd(data) % 60;
+                    self.dirty = true;
+                }
+                0x05 => {
+                    self.hour = Self::from_bcd(data) % 24;
+                    self.dirty = true;
+                }
+                _ => (), // rtc read or alarm:ignore
+            },
+            // rtc page 0 write:0x04 index, 0x05 value
+            0x04 if self.registers[0x07] == 0x00 => {
+                self.write_rtc_page(self.registers[0x04], self.registers[0x05]);
+                self.dirty = true;
+            }
+            _ => (), // unknown:ignore
+        }
+    }
+
+    fn read_data(&self, ram: &[u8]) -> u8 {
+        let address: u16 = self.get_address();
+        match self.registers[0x06] >> 1 {
+            0x01 => read_ram_bank(ram, 0x00, address, self.log_mode),
+            0x02 => match address {
+                0x06 => Self::to_bcd(self.min),
+                0x07 => Self::to_bcd(self.hour),
+                _ => 0x00,
+            },
+            0x04 => self.read_rtc_page(self.registers[0x04]),
+            _ => 0x00,
+        }
+    }
+}
+
+impl Mbc for TAMA5 {
+    fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            0x0000..=0x7fff => (), // no register
+            0xa000..=0xbfff => {
+                if address & 0x0001 == 0x0001 {
+                    self.register = value;
+                } else if self.register < 0x08 {
+                    self.registers[self.register as usize] = value & 0x0f;
+                    if self.register == 0x07 {
+                        self.execute_command(ram);
+                    }
+                }
+                // read only:ignore
+            }
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(rom, 0x00, address),
+            0x4000..=0x7fff => read_rom_bank(rom, self.get_rom_bank(), address),
+
+            0xa000..=0xbfff => match (address & 0x0001, self.register) {
+                (0x0001, _) => 0xff,
+                (_, 0x0a) => 0xf1, // ready
+                (_, 0x0c) => self.read_data(ram) & 0x0f | 0xf0,
+                (_, 0x0d) => self.read_data(ram) >> 4 | 0xf0,
+                _ => 0xf0,
+            },
+
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.enable_timer {
+            self.counter += cycles as u32;
+            if self.counter >= Common::CPU_CLOCK {
+                self.counter -= Common::CPU_CLOCK;
+                self.add_rtc_seconds(1);
+            }
+        }
+    }
+
+    /// sec, min, hour, week, day, month, year, timer enable and unix time (u64)
+    fn serialize(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![
+            self.sec,
+            self.min,
+            self.hour,
+            self.week,
+            self.day,
+            self.month,
+            self.year,
+            self.enable_timer as u8,
+        ];
+        data.extend(get_current_time_sec().to_le_bytes());
+        data
+    }
+
+    fn deserialize(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE {
+            Log::info(
+                format!("{: <5}:unexpected {} byte", "RTC", data.len()),
+                self.log_mode,
+            );
+            return;
+        }
+
+        self.sec = data[0] % 60;
+        self.min = data[1] % 60;
+        self.hour = data[2] % 24;
+        self.week = data[3] % 7;
+        self.day = data[4].clamp(1, 31);
+        self.month = data[5].clamp(1, 12);
+        self.year = data[6] % 100;
+        self.enable_timer = data[7] == 0x01;
+
+        let mut time: [u8; 8] = [0; 8];
+        time.copy_from_slice(&data[8..]);
+        let last_time_sec: u64 = u64::from_le_bytes(time);
+
+        self.add_rtc_seconds(get_elapsed_time_sec(last_time_sec));
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
@@ -0,0 +1,46 @@
+use super::{read_rom_bank, Mbc};
+
+/* Wisdom Tree:32KB banks selected by the written address */
+pub struct WisdomTree {
+    rom_bank: u8, // 32KB bank
+}
+
+impl WisdomTree {
+    pub fn new(_log_mode: u8) -> Self {
+        WisdomTree { rom_bank: 0x00 }
+    }
+
+    /// the title has the publisher name, the header type is unreliable
+    pub fn detect(rom: &[u8]) -> bool {
+        rom.len() > 0x8000
+            && rom[0x0134..0x0144]
+                .windows(11)
+                .any(|name| name == b"WISDOM TREE" || name == b"WISDOM\0TREE")
+    }
+}
+
+impl Mbc for WisdomTree {
+    fn write(&mut self, address: u16, _value: u8, _ram: &mut [u8]) {
+        match address {
+            0x0000..=0x3fff => self.rom_bank = address as u8, // the value is ignored
+            0x4000..=0x7fff => (),                            // no register
+            0xa000..=0xbfff => (),                            // no ram
+            _ => {
+                panic!("write address error:{:#08x}", address)
+            }
+        }
+    }
+
+    fn read(&self, address: u16, rom: &[u8], _ram: &[u8]) -> u8 {
+        match address {
+            0x0000..=0x7fff => {
+                let bank: usize = (self.rom_bank as usize) << 1 | (address >> 14) as usize;
+                read_rom_bank(rom, bank, address)
+            }
+            0xa000..=0xbfff => 0xff, // no ram
+            _ => {
+                panic!("unexpected address:{:#08x}", address)
+            }
+        }
+    }
+}
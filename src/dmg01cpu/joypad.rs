@@ -1,9 +1,17 @@
 use sdl2::keyboard::Keycode;
+use std::collections::VecDeque;
 
 pub struct Joypad {
     p1joyp: u8, // ff00 p1/joyp
     state: u8,
     pub irq: bool,
+    /* SGB */
+    is_sgb: bool,
+    packet: [u8; 16],
+    packet_bit: Option<u8>,          // receiving, 128 bits and a stop bit
+    pub packets: VecDeque<[u8; 16]>, // received
+    players: u8,                     // mlt_req:1, 2 or 4
+    player: u8,                      // current joypad
 }
 
 impl Joypad {
@@ -12,6 +20,50 @@ impl Joypad {
             p1joyp: 0xff,
             state: 0xff,
             irq: false,
+            is_sgb: false,
+            packet: [0; 16],
+            packet_bit: None,
+            packets: VecDeque::new(),
+            players: 1,
+            player: 0,
+        }
+    }
+
+    pub fn set_sgb_mode(&mut self, is_sgb: bool) {
+        self.is_sgb = is_sgb;
+    }
+
+    pub fn set_players(&mut self, players: u8) {
+        self.players = players;
+        self.player = 0;
+    }
+
+    /// sgb:both low resets, then p14 low sends 0 and p15 low sends 1 (lsb first)
+    fn receive_packet(&mut self, previous: u8, value: u8) {
+        match value {
+            0x00 => {
+                self.packet = [0; 16];
+                self.packet_bit = Some(0);
+            }
+            0x10 | 0x20 if previous == 0x30 => match self.packet_bit {
+                Some(bit) if bit < 128 => {
+                    if value == 0x10 {
+                        self.packet[(bit >> 3) as usize] |= 0x01 << (bit & 0x07);
+                    }
+                    self.packet_bit = Some(bit + 1);
+                }
+                Some(_) => {
+                    // stop bit
+                    self.packets.push_back(self.packet);
+                    self.packet_bit = None;
+                }
+                None => (),
+            },
+            // mlt_req:the next joypad on the rising edge of p15
+            0x30 if self.packet_bit.is_none() && previous & 0x20 == 0 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => (),
         }
     }
 
@@ -47,7 +99,13 @@ impl Joypad {
 
     pub fn write(&mut self, address: u16, value: u8) {
         match address {
-            0xff00 => self.p1joyp = (self.p1joyp & 0xcf) | (value & 0x30), // 0x30:select button type
+            0xff00 => {
+                let previous: u8 = self.p1joyp & 0x30;
+                self.p1joyp = (self.p1joyp & 0xcf) | (value & 0x30); // 0x30:select button type
+                if self.is_sgb {
+                    self.receive_packet(previous, value & 0x30);
+                }
+            }
             _ => panic!("unexepted address {:#08x}", address),
         }
     }
@@ -55,12 +113,21 @@ impl Joypad {
     pub fn read(&self, address: u16) -> u8 {
         match address {
             0xff00 => {
-                if self.p1joyp & 0x10 == 0 {
+                // sgb:only the first joypad has the keys
+                let state: u8 = match self.player {
+                    0 => self.state,
+                    _ => 0xff,
+                };
+
+                if self.p1joyp & 0x30 == 0x30 && self.players > 1 {
+                    // sgb:0x0f - joypad number
+                    (self.p1joyp & 0xf0) | (0x0f - self.player)
+                } else if self.p1joyp & 0x10 == 0 {
                     // 0x10:direction
-                    (self.p1joyp & 0xf0) | (self.state >> 4) & 0x0f
+                    (self.p1joyp & 0xf0) | (state >> 4) & 0x0f
                 } else if self.p1joyp & 0x20 == 0 {
                     // 0x20:button
-                    (self.p1joyp & 0xf0) | self.state & 0x0f
+                    (self.p1joyp & 0xf0) | state & 0x0f
                 } else {
                     self.p1joyp
                 }
@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// bg, obp0 and obp1, four rgb555 colors each
+pub type DmgPalettes = [[u16; 4]; 3];
+
+const fn rgb(color: u32) -> u16 {
+    let red: u32 = (color >> 19) & 0x1f;
+    let green: u32 = (color >> 11) & 0x1f;
+    let blue: u32 = (color >> 3) & 0x1f;
+    (blue << 10 | green << 5 | red) as u16
+}
+
+const fn same(colors: [u16; 4]) -> DmgPalettes {
+    [colors, colors, colors]
+}
+
+pub const POCKET_GRAY: DmgPalettes =
+    same([rgb(0xffffff), rgb(0xa9a9a9), rgb(0x545454), rgb(0x000000)]);
+
+pub const DMG_GREEN: DmgPalettes =
+    same([rgb(0x9bbc0f), rgb(0x8bac0f), rgb(0x306230), rgb(0x0f380f)]);
+
+/// light green tint of the earlier versions
+pub const CLASSIC: DmgPalettes = same([rgb(0xe6ffe6), rgb(0x91aa91), rgb(0x3c553c), rgb(0x000000)]);
+
+/// cgb boot rom palettes, rgb555
+const CGB_COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000, // 0
+    0x639f, 0x4279, 0x15b0, 0x04cb, // 1
+    0x7fff, 0x6e31, 0x454a, 0x0000, // 2
+    0x7fff, 0x1bef, 0x0200, 0x0000, // 3
+    0x7fff, 0x421f, 0x1cf2, 0x0000, // 4
+    0x7fff, 0x5294, 0x294a, 0x0000, // 5
+    0x7fff, 0x03ff, 0x012f, 0x0000, // 6
+    0x7fff, 0x03ef, 0x01d6, 0x0000, // 7
+    0x7fff, 0x42b5, 0x3dc8, 0x0000, // 8
+    0x7e74, 0x03ff, 0x0180, 0x0000, // 9
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b, // 10
+    0x7ed6, 0x4bff, 0x2175, 0x0000, // 11
+    0x53ff, 0x4a5f, 0x7e52, 0x0000, // 12
+    0x4fff, 0x7ed2, 0x3a4c, 0x1ce0, // 13
+    0x03ed, 0x7fff, 0x255f, 0x0000, // 14
+    0x036a, 0x021f, 0x03ff, 0x7fff, // 15
+    0x7fff, 0x01df, 0x0112, 0x0000, // 16
+    0x231f, 0x035f, 0x00f2, 0x0009, // 17
+    0x7fff, 0x03ea, 0x011f, 0x0000, // 18
+    0x299f, 0x001a, 0x000c, 0x0000, // 19
+    0x7fff, 0x027f, 0x001f, 0x0000, // 20
+    0x7fff, 0x03e0, 0x0206, 0x0120, // 21
+    0x7fff, 0x7eeb, 0x001f, 0x7c00, // 22
+    0x7fff, 0x3fff, 0x7e00, 0x001f, // 23
+    0x7fff, 0x03ff, 0x001f, 0x0000, // 24
+    0x03ff, 0x001f, 0x000c, 0x0000, // 25
+    0x7fff, 0x033f, 0x0193, 0x0000, // 26
+    0x0000, 0x4200, 0x037f, 0x7fff, // 27
+    0x7fff, 0x7e8c, 0x7c00, 0x0000, // 28
+    0x7fff, 0x1bef, 0x6180, 0x0000, // 29
+];
+
+/// cgb boot rom combinations:obj0, obj1 and bg as color offsets
+/// some start in the middle of a palette like the boot rom
+const CGB_COMBINATIONS: [(u8, u8, u8); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),         // 0:unknown titles
+    (18 * 4, 18 * 4, 18 * 4),       // 1
+    (20 * 4, 20 * 4, 20 * 4),       // 2
+    (24 * 4, 24 * 4, 24 * 4),       // 3
+    (9 * 4, 9 * 4, 9 * 4),          // 4
+    (0, 0, 0),                      // 5
+    (27 * 4, 27 * 4, 27 * 4),       // 6
+    (5 * 4, 5 * 4, 5 * 4),          // 7
+    (12 * 4, 12 * 4, 12 * 4),       // 8
+    (26 * 4, 26 * 4, 26 * 4),       // 9
+    (16 * 4, 8 * 4, 8 * 4),         // 10
+    (4 * 4, 28 * 4, 28 * 4),        // 11
+    (4 * 4, 2 * 4, 2 * 4),          // 12
+    (3 * 4, 4 * 4, 4 * 4),          // 13
+    (4 * 4, 29 * 4, 29 * 4),        // 14
+    (28 * 4, 4 * 4, 28 * 4),        // 15
+    (2 * 4, 17 * 4, 2 * 4),         // 16
+    (16 * 4, 16 * 4, 8 * 4),        // 17
+    (4 * 4, 4 * 4, 7 * 4),          // 18
+    (4 * 4, 4 * 4, 18 * 4),         // 19
+    (4 * 4, 4 * 4, 20 * 4),         // 20
+    (19 * 4, 19 * 4, 9 * 4),        // 21
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4), // 22
+    (17 * 4, 17 * 4, 2 * 4),        // 23
+    (4 * 4, 4 * 4, 2 * 4),          // 24
+    (4 * 4, 4 * 4, 3 * 4),          // 25
+    (28 * 4, 28 * 4, 0),            // 26
+    (3 * 4, 3 * 4, 0),              // 27
+    (0, 0, 4),                      // 28
+    (18 * 4, 22 * 4, 18 * 4),       // 29
+    (20 * 4, 22 * 4, 20 * 4),       // 30
+    (24 * 4, 22 * 4, 24 * 4),       // 31
+    (16 * 4, 22 * 4, 8 * 4),        // 32
+    (17 * 4, 4 * 4, 13 * 4),        // 33
+    (28 * 4 - 1, 0, 14 * 4),        // 34
+    (28 * 4 - 1, 4 * 4, 15 * 4),    // 35
+    (19 * 4, 22 * 4, 9 * 4),        // 36
+    (16 * 4, 28 * 4, 10 * 4),       // 37
+    (4 * 4, 23 * 4, 28 * 4),        // 38
+    (17 * 4, 22 * 4, 2 * 4),        // 39
+    (4 * 4, 0, 2 * 4),              // 40
+    (4 * 4, 28 * 4, 3 * 4),         // 41
+    (28 * 4, 3 * 4, 0),             // 42
+    (3 * 4, 28 * 4, 4 * 4),         // 43
+    (21 * 4, 28 * 4, 4 * 4),        // 44
+    (3 * 4, 28 * 4, 0),             // 45
+    (25 * 4, 3 * 4, 28 * 4),        // 46
+    (0, 28 * 4, 8 * 4),             // 47
+    (4 * 4, 3 * 4, 28 * 4),         // 48
+    (28 * 4, 3 * 4, 6 * 4),         // 49
+    (4 * 4, 28 * 4, 29 * 4),        // 50
+];
+
+/// cgb boot rom titles:checksum, 4th letter of the title and combination
+/// the 4th letter tells apart the titles with the same checksum
+const CGB_TITLES: [(u8, Option<u8>, u8); 93] = [
+    (0x88, None, 4),  // ALLEY WAY
+    (0x16, None, 5),  // YAKUMAN
+    (0x36, None, 35), // BASEBALL
+    (0xd1, None, 34), // TENNIS
+    (0xdb, None, 3),  // TETRIS
+    (0xf2, None, 31), // QIX
+    (0x3c, None, 15), // DR.MARIO
+    (0x8c, None, 10), // RADARMISSION
+    (0x92, None, 5),  // F1RACE
+    (0x3d, None, 19), // YOSSY NO TAMAGO
+    (0x5c, None, 36),
+    (0x58, None, 7),  // X
+    (0xc9, None, 37), // MARIOLAND2
+    (0x3e, None, 30), // YOSSY NO COOKIE
+    (0x70, None, 44), // ZELDA
+    (0x1d, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31), // TETRIS FLASH
+    (0x19, None, 20), // DONKEY KONG
+    (0x35, None, 5),  // MARIO'S PICROSS
+    (0xa8, None, 33),
+    (0x14, None, 13), // POKEMON RED
+    (0xaa, None, 14), // POKEMON GREEN
+    (0x75, None, 5),  // PICROSS 2
+    (0x95, None, 29), // YOSSY NO PANEPON
+    (0x99, None, 5),  // KIRAKIRA KIDS
+    (0x34, None, 18), // GAMEBOY GALLERY
+    (0x6f, None, 9),  // POCKETCAMERA
+    (0x15, None, 3),
+    (0xff, None, 2),  // BALLOON KID
+    (0x97, None, 26), // KINGOFTHEZOO
+    (0x4b, None, 25), // DMG FOOTBALL
+    (0x90, None, 25), // WORLD CUP
+    (0x17, None, 41), // OTHELLO
+    (0x10, None, 42), // SUPER RC PRO-AM
+    (0x39, None, 26), // DYNABLASTER
+    (0xf7, None, 45), // BOY AND BLOB GB2
+    (0xf6, None, 42), // MEGAMAN
+    (0xa2, None, 45), // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4e, None, 38), // WAVERACE
+    (0xc3, None, 26),
+    (0x68, None, 42), // LOLO2
+    (0xe0, None, 30), // YOSHI'S COOKIE
+    (0x8b, None, 41), // MYSTIC QUEST
+    (0xf0, None, 34),
+    (0xce, None, 34), // TOPRANKINGTENNIS
+    (0x0c, None, 5),  // MANSELL
+    (0x29, None, 42), // MEGAMAN3
+    (0xe8, None, 6),  // SPACE INVADERS
+    (0xb7, None, 5),  // GAME&WATCH
+    (0x86, None, 33), // DONKEYKONGLAND95
+    (0x9a, None, 25), // ASTEROIDS/MISCMD
+    (0x52, None, 42), // STREET FIGHTER 2
+    (0x01, None, 42), // DEFENDER/JOUST
+    (0x9d, None, 40), // KILLERINSTINCT95
+    (0x71, None, 2),  // TETRIS BLAST
+    (0x9c, None, 16), // PINOCCHIO
+    (0xbd, None, 25),
+    (0x5d, None, 42), // BA.TOSHINDEN
+    (0x6d, None, 42), // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3f, None, 0),  // TETRIS PLUS
+    (0x8b, None, 39), // DONKEYKONGLAND 3, MYSTIC QUEST comes first like the boot rom
+    (0xb3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 32), // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25), // GOLF
+    (0xa5, Some(b'A'), 6),  // SOLARSTRIKER
+    (0xc6, Some(b'A'), 32), // GBWARS
+    (0xd3, Some(b'R'), 12), // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11), // POKEMON BLUE
+    (0x18, Some(b'K'), 39), // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18), // GAMEBOY GALLERY2
+    (0x6a, Some(b'K'), 39), // DONKEYKONGLAND 2
+    (0xbf, Some(b' '), 24), // KID ICARUS
+    (0x0d, Some(b'R'), 31), // TETRIS2
+    (0xf4, Some(b'-'), 50),
+    (0xb3, Some(b'U'), 17), // MOGURANYA
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),  // GALAXIAN
+    (0xa5, Some(b'R'), 27), // BT2RAGNAROKWORLD
+    (0xc6, Some(b' '), 0),  // KEN GRIFFEY JR
+    (0xd3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41), // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41), // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),  // MILLI/CENTI/PEDE
+    (0x6a, Some(b'I'), 19), // MARIO & YOSHI
+    (0xbf, Some(b'C'), 34), // SOCCER
+    (0x0d, Some(b'E'), 23), // POKEBOM
+    (0xf4, Some(b' '), 18), // G&W GALLERY
+    (0xb3, Some(b'R'), 29), // TETRIS ATTACK
+];
+
+/// bg, obp0 and obp1 of the combination
+fn get_combination(index: u8) -> DmgPalettes {
+    let (obj0, obj1, bg) = CGB_COMBINATIONS[index as usize];
+    let get = |offset: u8| -> [u16; 4] {
+        let offset: usize = offset as usize;
+        match CGB_COLORS[offset..offset + 4].try_into() {
+            Ok(result) => result,
+            Err(error) => panic!("cgb palette error:{}", error),
+        }
+    };
+    [get(bg), get(obj0), get(obj1)]
+}
+
+/// nintendo titles are looked up by the title checksum
+pub fn get_cgb_palettes(rom: &[u8]) -> DmgPalettes {
+    // 0x014b:old licensee code, 0x33:new licensee code in 0x0144-0x0145
+    let is_nintendo: bool = match rom[0x014b] {
+        0x01 => true,
+        0x33 => &rom[0x0144..0x0146] == b"01",
+        _ => false,
+    };
+    if !is_nintendo {
+        return get_combination(0);
+    }
+
+    let checksum: u8 = rom[0x0134..=0x0143]
+        .iter()
+        .fold(0, |sum: u8, value| sum.wrapping_add(*value));
+    let combination: u8 = CGB_TITLES
+        .iter()
+        .find(|(title_checksum, fourth_letter, _)| {
+            *title_checksum == checksum
+                && fourth_letter.is_none_or(|letter| letter == rom[0x0134 + 3])
+        })
+        .map_or(0, |(_, _, combination)| *combination);
+    get_combination(combination)
+}
+
+/// bg=, obj0=, obj1= lines of four rrggbb colors, the obj palettes default to bg
+pub fn load_file(file: &str) -> Result<DmgPalettes, String> {
+    let file: File = match File::open(file) {
+        Ok(result) => result,
+        Err(error) => return Err(format!("palette file open error:{}", error)),
+    };
+
+    let mut palettes: [Option<[u16; 4]>; 3] = [None; 3];
+    for line in BufReader::new(file).lines() {
+        let line: String = match line {
+            Ok(result) => result,
+            Err(error) => return Err(format!("palette file read error:{}", error)),
+        };
+        let line: &str = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, colors) = match line.split_once('=') {
+            Some(result) => result,
+            None => return Err(format!("palette file error:{}", line)),
+        };
+        let index: usize = match name.trim() {
+            "bg" => 0,
+            "obj0" => 1,
+            "obj1" => 2,
+            _ => return Err(format!("palette file error:unknown palette {}", name)),
+        };
+
+        let values: Vec<u32> = match colors
+            .split(',')
+            .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16))
+            .collect()
+        {
+            Ok(result) => result,
+            Err(error) => return Err(format!("palette file error:{} {}", colors, error)),
+        };
+        let values: [u32; 4] = match values.try_into() {
+            Ok(result) => result,
+            Err(_) => return Err(format!("palette file error:{} needs 4 colors", name)),
+        };
+        palettes[index] = Some(values.map(rgb));
+    }
+
+    let bg: [u16; 4] = match palettes[0] {
+        Some(result) => result,
+        None => return Err("palette file error:bg is missing".to_string()),
+    };
+    Ok([bg, palettes[1].unwrap_or(bg), palettes[2].unwrap_or(bg)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// nintendo rom with the title checksum and the 4th letter
+    fn new_rom(checksum: u8, fourth_letter: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = vec![0; 0x8000];
+        rom[0x014b] = 0x01;
+        rom[0x0134 + 3] = fourth_letter;
+        rom[0x0134] = checksum.wrapping_sub(fourth_letter);
+        rom
+    }
+
+    #[test]
+    fn title_checksum_and_fourth_letter() {
+        // POKEMON RED:red bg, green obp0
+        let palettes: DmgPalettes = get_cgb_palettes(&new_rom(0x14, b'E'));
+        assert_eq!(palettes[0], [0x7fff, 0x421f, 0x1cf2, 0x0000]);
+        assert_eq!(palettes[1], [0x7fff, 0x1bef, 0x0200, 0x0000]);
+
+        // 0x61:POKEMON BLUE and VEGAS STAKES
+        assert_eq!(get_cgb_palettes(&new_rom(0x61, b'E')), get_combination(11));
+        assert_eq!(get_cgb_palettes(&new_rom(0x61, b'A')), get_combination(41));
+        assert_eq!(get_cgb_palettes(&new_rom(0x61, b'Z')), get_combination(0));
+
+        // unlicensed
+        let mut rom: Vec<u8> = new_rom(0x14, b'E');
+        rom[0x014b] = 0x00;
+        assert_eq!(get_cgb_palettes(&rom), get_combination(0));
+    }
+
+    #[test]
+    fn broken_palette_file_is_an_error() {
+        assert!(load_file("no-such-palette").is_err());
+
+        let file: std::path::PathBuf = std::env::temp_dir().join("simple-rustboy-palette.txt");
+        for (text, is_ok) in [
+            ("bg=ffffff,aaaaaa,555555,000000\n", true),
+            ("bg=ffffff,aaaaaa,555555\n", false),
+            ("bg=ffffff,aaaaaa,555555,black\n", false),
+            ("obj0=ffffff,aaaaaa,555555,000000\n", false),
+            ("window=ffffff,aaaaaa,555555,000000\n", false),
+        ] {
+            std::fs::write(&file, text).unwrap();
+            assert_eq!(load_file(file.to_str().unwrap()).is_ok(), is_ok, "{}", text);
+        }
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use super::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::collections::VecDeque;
+
+const OAM_SCAN_DOTS: u16 = 80;
+const LINE_DOTS: u16 = 456;
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    color_no: u8,
+    flags: u8,    // 0x80:obj-to-bg priority, 0x10:dmg palette, 0x07:cgb palette
+    index: usize, // oam index
+}
+
+/* Pixel FIFO */
+pub struct Fifo {
+    bg: VecDeque<(u8, u8)>, // color number, cgb attributes
+    sprite: VecDeque<SpritePixel>,
+    sprites: Vec<usize>, // oam index, not fetched yet
+    fetcher_step: u8,    // 0-5:fetch, 6:push
+    fetcher_x: u8,       // tile
+    tile_no: u8,
+    attributes: u8,
+    tile: (u8, u8),
+    lx: u8,      // pixel x
+    discard: u8, // scx fine scroll
+    wait: u8,    // dots, the bg fetcher keeps running
+    stall: u8,   // dots, the bg fetcher is paused
+    window: bool,
+    window_start: Option<(u8, u8)>, // (x, skip)
+    sprite_tile: Option<u8>,        // tile column of the last sprite fetch
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Fifo {
+            bg: VecDeque::with_capacity(8),
+            sprite: VecDeque::with_capacity(8),
+            sprites: Vec::with_capacity(10),
+            fetcher_step: 0,
+            fetcher_x: 0,
+            tile_no: 0,
+            attributes: 0,
+            tile: (0, 0),
+            lx: 0,
+            discard: 0,
+            wait: 0,
+            stall: 0,
+            window: false,
+            window_start: None,
+            sprite_tile: None,
+        }
+    }
+}
+
+impl PPU {
+    fn start_fifo_line(&mut self) {
+        self.fifo.bg.clear();
+        self.fifo.sprite.clear();
+        self.fifo.sprites = self.scan_oam();
+        self.fifo.fetcher_step = 0;
+        self.fifo.fetcher_x = 0;
+        self.fifo.lx = 0;
+        self.fifo.discard = self.sclx & 0x07;
+        self.fifo.wait = 0;
+        self.fifo.stall = 6; // the first fetch is thrown away
+        self.fifo.window = false;
+        self.fifo.window_start = self.start_window_line();
+        self.fifo.sprite_tile = None;
+    }
+
+    /// tile row of the background or the window
+    fn get_fetcher_row(&self) -> (u16, u8) {
+        if self.fifo.window {
+            (self.get_window_tile_map_base(), self.window_line)
+        } else {
+            (self.get_bg_tile_map_base(), self.scly.wrapping_add(self.ly))
+        }
+    }
+
+    /// 2 dots per step:tile number, data low, data high, then push when the fifo is empty
+    fn fetch_bg(&mut self) {
+        match self.fifo.fetcher_step {
+            1 => {
+                let (tile_map_base, y) = self.get_fetcher_row();
+                let tile_x: u8 = match self.fifo.window {
+                    true => self.fifo.fetcher_x,
+                    _ => (self.sclx >> 3).wrapping_add(self.fifo.fetcher_x),
+                };
+                let tile_map_addr =
+                    tile_map_base | ((tile_x & 0x1f) as u16 + ((y as u16 >> 3) << 5));
+                self.fifo.tile_no = self.vram[tile_map_addr as usize];
+                self.fifo.attributes = self.get_tile_attributes(tile_map_addr);
+            }
+            5 => {
+                let (_, y) = self.get_fetcher_row();
+                self.fifo.tile =
+                    self.fetch_bg_tile(self.fifo.tile_no, y & 0x07, self.fifo.attributes);
+            }
+            6 => {
+                if self.fifo.bg.is_empty() {
+                    let attributes: u8 = self.fifo.attributes;
+                    for offset_x in 0..8 {
+                        // 0x20:x-flip
+                        let bitpos = match attributes & 0x20 {
+                            0x20 => offset_x,
+                            _ => 7 - offset_x,
+                        };
+                        // 0x01:bg display, cgb:bg and window master priority
+                        let color_no: u8 = match self.lcdc & 0x01 == 0x01 || self.is_cgb {
+                            true => self.get_color_no(self.fifo.tile, bitpos),
+                            _ => 0,
+                        };
+                        self.fifo.bg.push_back((color_no, attributes));
+                    }
+                    self.fifo.fetcher_step = 0;
+                    self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+                }
+                return;
+            }
+            _ => (),
+        }
+        self.fifo.fetcher_step += 1;
+    }
+
+    /// merge into the sprite fifo, earlier sprites keep their opaque pixels
+    /// cgb:the smaller oam index wins
+    fn fetch_sprite(&mut self, index: usize) {
+        let entry_addr: usize = index << 2;
+        let sprite_x: u8 = self.oam[entry_addr + 1];
+        let flags: u8 = self.oam[entry_addr + 3];
+        let tile: (u8, u8) = self.fetch_sprite_row(index);
+
+        // pixels left of the current x (sprite_x < 8)
+        let skip: u8 = (self.fifo.lx + 8).saturating_sub(sprite_x);
+        for offset_x in skip..8 {
+            let bitpos = match flags & 0x20 {
+                0x20 => offset_x, // x-flip
+                _ => 7 - offset_x,
+            };
+            let pixel = SpritePixel {
+                color_no: self.get_color_no(tile, bitpos),
+                flags,
+                index,
+            };
+
+            let position: usize = (offset_x - skip) as usize;
+            let is_cgb: bool = self.is_cgb;
+            match self.fifo.sprite.get_mut(position) {
+                Some(current) if current.color_no == 0 => *current = pixel,
+                Some(current) if is_cgb && pixel.color_no != 0 && pixel.index < current.index => {
+                    *current = pixel
+                }
+                Some(_) => (),
+                None => self.fifo.sprite.push_back(pixel),
+            }
+        }
+    }
+
+    /// palette index of the pixel
+    fn mix_pixel(&self, bg: (u8, u8), sprite: Option<SpritePixel>) -> u8 {
+        let (bg_color_no, attributes) = bg;
+        // 0x80:priority of the sprite or the cgb bg attributes, cgb:0x01 clear puts sprites on top
+        let is_bg_priority = |flags: u8| {
+            bg_color_no != 0
+                && (flags | attributes) & 0x80 == 0x80
+                && (self.lcdc & 0x01 == 0x01 || !self.is_cgb)
+        };
+
+        match sprite {
+            // 0x02:obj display, 0:transparent
+            Some(pixel)
+                if self.lcdc & 0x02 == 0x02
+                    && pixel.color_no != 0
+                    && !is_bg_priority(pixel.flags) =>
+            {
+                self.get_obj_index(pixel.color_no, pixel.flags)
+            }
+            _ => self.get_bg_index(bg_color_no, attributes),
+        }
+    }
+
+    /// one dot of mode 3, true at the end of the line
+    fn step_fifo(&mut self) -> bool {
+        if self.fifo.wait > 0 {
+            self.fifo.wait -= 1;
+            self.fetch_bg();
+            return false;
+        }
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+
+        if let Some((start_x, skip)) = self.fifo.window_start {
+            if !self.fifo.window && self.fifo.discard == 0 && self.fifo.lx == start_x {
+                self.fifo.window = true;
+                self.fifo.bg.clear();
+                self.fifo.fetcher_step = 0;
+                self.fifo.fetcher_x = 0;
+                self.fifo.discard = skip; // wx 0-6
+            }
+        }
+
+        // 0x02:obj display, the smaller x first
+        if self.lcdc & 0x02 == 0x02 {
+            let lx: u8 = self.fifo.lx;
+            let found = (0..self.fifo.sprites.len())
+                .filter(|&position| self.oam[(self.fifo.sprites[position] << 2) + 1] <= lx + 8)
+                .min_by_key(|&position| self.oam[(self.fifo.sprites[position] << 2) + 1]);
+            if let Some(position) = found {
+                // the first sprite on a tile waits for the bg fetcher (0-5 dots)
+                let fine_x: u8 = match self.fifo.window {
+                    true => (lx + 7).wrapping_sub(self.wx),
+                    _ => lx.wrapping_add(self.sclx),
+                };
+                if self.fifo.sprite_tile != Some(fine_x >> 3) {
+                    self.fifo.sprite_tile = Some(fine_x >> 3);
+                    self.fifo.wait = 5 - std::cmp::min(5, fine_x & 0x07);
+                }
+
+                let index: usize = self.fifo.sprites.remove(position);
+                self.fetch_sprite(index);
+                self.fifo.stall = SPRITE_FETCH_DOTS - 1;
+                return false;
+            }
+        }
+
+        self.fetch_bg();
+
+        if let Some(bg) = self.fifo.bg.pop_front() {
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+                return false;
+            }
+
+            let sprite: Option<SpritePixel> = self.fifo.sprite.pop_front();
+            let index: u8 = self.mix_pixel(bg, sprite);
+            self.set_pixel(self.fifo.lx, index);
+            self.fifo.lx += 1;
+        }
+
+        self.fifo.lx >= SCREEN_WIDTH
+    }
+
+    /// dot based mode transitions, mode 3 ends when 160 pixels are pushed
+    pub(super) fn update_fifo(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.counter += 1;
+
+            // 0x03:mode flag
+            match self.stat & 0x03 {
+                0x02 => {
+                    // during searching oam-ram
+                    if self.counter >= OAM_SCAN_DOTS {
+                        self.stat = self.get_masked_status() | 0x03; // 0x03:during trasfer
+                        self.update_stat();
+                        self.start_fifo_line();
+                    }
+                }
+                0x03 => {
+                    // during trasfering data to lcd driver
+                    if self.step_fifo() {
+                        self.end_window_line(self.fifo.window);
+                        self.stat = self.get_masked_status(); // 0x00:during h-blank
+                        self.update_stat();
+                        self.hblank = true;
+                    }
+                }
+                0x00 => {
+                    // during h-blank
+                    if self.counter >= LINE_DOTS {
+                        self.counter = 0;
+                        self.ly += 1;
+
+                        if self.ly >= SCREEN_HEIGHT {
+                            self.stat = self.get_masked_status() | 0x01; // 0x01:during v-blank
+                            self.irq_vblank = true;
+                        } else {
+                            self.stat = self.get_masked_status() | 0x02; // 0x02:during searching oam-ram
+                        }
+
+                        self.update_stat();
+                    }
+                }
+                _ => self.update_vblank(), // 0x01:during v-blank
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use super::joypad::Joypad;
+use super::ppu::{DMG_SHADES, PPU};
+use super::Log;
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+const SCREEN_WIDTH: usize = 160;
+const SCREEN_HEIGHT: usize = 144;
+const SCREEN_X: usize = 48; // game screen in the border
+const SCREEN_Y: usize = 40;
+const ATTRIBUTE_WIDTH: usize = 20; // 8x8 cells
+const ATTRIBUTE_HEIGHT: usize = 18;
+const ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = 90; // 4 cells per byte
+
+/* Super Game Boy */
+pub struct Sgb {
+    log_mode: u8,
+    command: Vec<u8>, // packets of the current command
+    palettes: [[u16; 4]; 4],
+    system_palettes: [u16; 0x800], // pal_trn:512 palettes
+    attributes: [u8; ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT], // palette number per cell
+    attribute_files: [u8; 0x1000], // attr_trn
+    border_tiles: [u8; 0x2000],    // chr_trn:256 tiles, snes 4bpp
+    border_map: [u8; 0x800],       // pct_trn:32x32 entries
+    border_palettes: [u16; 0x40],  // pct_trn:palettes 4-7
+    mask: u8,                      // mask_en 0:cancel, 1:freeze, 2:black, 3:color 0
+    game_screen: [u16; SCREEN_WIDTH * SCREEN_HEIGHT], // kept while frozen
+    frame_buffer: [u16; SGB_WIDTH * SGB_HEIGHT], // rgb555
+}
+
+impl Sgb {
+    pub fn new(log_mode: u8) -> Self {
+        Sgb {
+            log_mode,
+            command: Vec::with_capacity(16 * 7),
+            palettes: [DMG_SHADES; 4],
+            system_palettes: [0; 0x800],
+            attributes: [0; ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT],
+            attribute_files: [0; 0x1000],
+            border_tiles: [0; 0x2000],
+            border_map: [0; 0x800],
+            border_palettes: [0; 0x40],
+            mask: 0,
+            game_screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_buffer: [0; SGB_WIDTH * SGB_HEIGHT],
+        }
+    }
+
+    fn get_color(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7fff
+    }
+
+    /// 0x07:number of packets of the command
+    pub fn receive(&mut self, packet: [u8; 16], ppu: &PPU, joypad: &mut Joypad) {
+        self.command.extend_from_slice(&packet);
+
+        let length: usize = std::cmp::max(self.command[0] & 0x07, 1) as usize;
+        if self.command.len() >= length * 16 {
+            let command: Vec<u8> = std::mem::take(&mut self.command);
+            self.execute(&command, ppu, joypad);
+        }
+    }
+
+    fn execute(&mut self, command: &[u8], ppu: &PPU, joypad: &mut Joypad) {
+        Log::info(
+            format!("{: <5}:{:#04x}", "SGB", command[0] >> 3),
+            self.log_mode,
+        );
+
+        match command[0] >> 3 {
+            0x00 => self.set_palette_pair(0, 1, command), // pal01
+            0x01 => self.set_palette_pair(2, 3, command), // pal23
+            0x02 => self.set_palette_pair(0, 3, command), // pal03
+            0x03 => self.set_palette_pair(1, 2, command), // pal12
+            0x04 => self.set_attribute_blocks(command),   // attr_blk
+            0x05 => self.set_attribute_lines(command),    // attr_lin
+            0x06 => self.set_attribute_division(command), // attr_div
+            0x07 => self.set_attribute_cells(command),    // attr_chr
+            0x0a => self.set_system_palettes(command),    // pal_set
+            0x0b => {
+                // pal_trn
+                let data: Vec<u8> = ppu.get_sgb_transfer();
+                for (index, color) in self.system_palettes.iter_mut().enumerate() {
+                    *color = Self::get_color(&data, index << 1);
+                }
+            }
+            0x11 => {
+                // mlt_req
+                let players: u8 = match command[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                joypad.set_players(players);
+            }
+            0x13 => {
+                // chr_trn 0x01:tiles 0x80-0xff
+                let offset: usize = ((command[1] & 0x01) as usize) << 12;
+                self.border_tiles[offset..offset + 0x1000].copy_from_slice(&ppu.get_sgb_transfer());
+            }
+            0x14 => {
+                // pct_trn
+                let data: Vec<u8> = ppu.get_sgb_transfer();
+                self.border_map.copy_from_slice(&data[..0x800]);
+                for (index, color) in self.border_palettes.iter_mut().enumerate() {
+                    *color = Self::get_color(&data, 0x800 + (index << 1));
+                }
+            }
+            0x15 => self
+                .attribute_files
+                .copy_from_slice(&ppu.get_sgb_transfer()), // attr_trn
+            0x16 => {
+                // attr_set 0x40:cancel mask
+                self.set_attribute_file((command[1] & 0x3f) as usize);
+                if command[1] & 0x40 == 0x40 {
+                    self.mask = 0;
+                }
+            }
+            0x17 => self.mask = command[1] & 0x03, // mask_en
+            _ => (),                               // sound, snes cpu access:not supported
+        }
+    }
+
+    /// color 0 is shared by all palettes
+    fn set_palette_pair(&mut self, first: usize, second: usize, command: &[u8]) {
+        let color0: u16 = Self::get_color(command, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        for color_no in 1..4 {
+            self.palettes[first][color_no] = Self::get_color(command, 1 + (color_no << 1));
+            self.palettes[second][color_no] = Self::get_color(command, 7 + (color_no << 1));
+        }
+    }
+
+    /// 6 bytes per block:control, palettes, x1, y1, x2, y2
+    fn set_attribute_blocks(&mut self, command: &[u8]) {
+        let count: usize = std::cmp::min(command[1], 18) as usize;
+
+        for block in command[2..].chunks_exact(6).take(count) {
+            let inside: u8 = block[1] & 0x03;
+            let outside: u8 = (block[1] >> 4) & 0x03;
+            // 0x01:inside, 0x02:border, 0x04:outside, the border follows a single area
+            let (control, border) = match block[0] & 0x07 {
+                0x01 => (0x03, inside),
+                0x04 => (0x06, outside),
+                control => (control, (block[1] >> 2) & 0x03),
+            };
+            let (x1, y1) = ((block[2] & 0x1f) as usize, (block[3] & 0x1f) as usize);
+            let (x2, y2) = ((block[4] & 0x1f) as usize, (block[5] & 0x1f) as usize);
+
+            for y in 0..ATTRIBUTE_HEIGHT {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    let is_inside: bool = x1 < x && x < x2 && y1 < y && y < y2;
+                    let is_block: bool = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let palette: Option<u8> = match (is_inside, is_block) {
+                        (true, _) if control & 0x01 == 0x01 => Some(inside),
+                        (false, true) if control & 0x02 == 0x02 => Some(border),
+                        (false, false) if control & 0x04 == 0x04 => Some(outside),
+                        _ => None,
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTRIBUTE_WIDTH + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 0x80:horizontal line, 0x60:palette, 0x1f:line number
+    fn set_attribute_lines(&mut self, command: &[u8]) {
+        for value in command[2..].iter().take(command[1] as usize) {
+            let line: usize = (value & 0x1f) as usize;
+            let palette: u8 = (value >> 5) & 0x03;
+
+            for y in 0..ATTRIBUTE_HEIGHT {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    let position: usize = match value & 0x80 {
+                        0x80 => y,
+                        _ => x,
+                    };
+                    if position == line {
+                        self.attributes[y * ATTRIBUTE_WIDTH + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 0x40:horizontal line, 0x30:on the line, 0x0c:above or left, 0x03:below or right
+    fn set_attribute_division(&mut self, command: &[u8]) {
+        let line: usize = command[2] as usize;
+
+        for y in 0..ATTRIBUTE_HEIGHT {
+            for x in 0..ATTRIBUTE_WIDTH {
+                let position: usize = match command[1] & 0x40 {
+                    0x40 => y,
+                    _ => x,
+                };
+                let shift: u8 = match position.cmp(&line) {
+                    std::cmp::Ordering::Less => 2,
+                    std::cmp::Ordering::Equal => 4,
+                    std::cmp::Ordering::Greater => 0,
+                };
+                self.attributes[y * ATTRIBUTE_WIDTH + x] = (command[1] >> shift) & 0x03;
+            }
+        }
+    }
+
+    /// from (x, y), 4 cells per byte from the upper bits
+    fn set_attribute_cells(&mut self, command: &[u8]) {
+        let (mut x, mut y) = (command[1] as usize, command[2] as usize);
+        let count: usize = u16::from_le_bytes([command[3], command[4]]) as usize;
+        let is_vertical: bool = command[5] & 0x01 == 0x01;
+
+        for cell in 0..std::cmp::min(count, ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT) {
+            let value: u8 = match command.get(6 + (cell >> 2)) {
+                Some(result) => *result,
+                None => break,
+            };
+            if x < ATTRIBUTE_WIDTH && y < ATTRIBUTE_HEIGHT {
+                self.attributes[y * ATTRIBUTE_WIDTH + x] =
+                    (value >> (6 - ((cell & 0x03) << 1))) & 0x03;
+            }
+
+            if is_vertical {
+                y += 1;
+                if y >= ATTRIBUTE_HEIGHT {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x >= ATTRIBUTE_WIDTH {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    /// 4 palette numbers, 0x80:apply the attribute file, 0x40:cancel mask
+    fn set_system_palettes(&mut self, command: &[u8]) {
+        for (palette, number) in self.palettes.iter_mut().zip(command[1..9].chunks_exact(2)) {
+            let offset: usize =
+                ((u16::from_le_bytes([number[0], number[1]]) & 0x01ff) as usize) << 2;
+            palette.copy_from_slice(&self.system_palettes[offset..offset + 4]);
+        }
+        let color0: u16 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        if command[9] & 0x80 == 0x80 {
+            self.set_attribute_file((command[9] & 0x3f) as usize);
+        }
+        if command[9] & 0x40 == 0x40 {
+            self.mask = 0;
+        }
+    }
+
+    fn set_attribute_file(&mut self, file: usize) {
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+
+        let offset: usize = file * ATTRIBUTE_FILE_SIZE;
+        for (cell, attribute) in self.attributes.iter_mut().enumerate() {
+            let value: u8 = self.attribute_files[offset + (cell >> 2)];
+            *attribute = (value >> (6 - ((cell & 0x03) << 1))) & 0x03;
+        }
+    }
+
+    /// 32x28 tiles, 0x8000:y-flip, 0x4000:x-flip, 0x1c00:palette 4-7, 0x00ff:tile
+    fn render_border(&mut self) {
+        let backdrop: u16 = self.palettes[0][0];
+
+        for tile_y in 0..SGB_HEIGHT / 8 {
+            for tile_x in 0..SGB_WIDTH / 8 {
+                let entry_addr: usize = (tile_y * 32 + tile_x) << 1;
+                let entry: u16 = u16::from_le_bytes([
+                    self.border_map[entry_addr],
+                    self.border_map[entry_addr + 1],
+                ]);
+                let tile_addr: usize = ((entry & 0x00ff) as usize) << 5;
+                let palette: usize = ((entry >> 10) & 0x03) as usize;
+
+                for offset_y in 0..8 {
+                    let row: usize = match entry & 0x8000 {
+                        0x8000 => 7 - offset_y,
+                        _ => offset_y,
+                    };
+                    // bitplanes 0 and 1, then 2 and 3
+                    let planes: [u8; 4] = [
+                        self.border_tiles[tile_addr + (row << 1)],
+                        self.border_tiles[tile_addr + (row << 1) + 1],
+                        self.border_tiles[tile_addr + 16 + (row << 1)],
+                        self.border_tiles[tile_addr + 16 + (row << 1) + 1],
+                    ];
+
+                    for offset_x in 0..8 {
+                        let bitpos: usize = match entry & 0x4000 {
+                            0x4000 => offset_x,
+                            _ => 7 - offset_x,
+                        };
+                        let color_no: usize = (0..4).fold(0, |color_no, plane| {
+                            color_no | (((planes[plane] >> bitpos) & 0x01) as usize) << plane
+                        });
+
+                        let ix: usize = (tile_y * 8 + offset_y) * SGB_WIDTH + tile_x * 8 + offset_x;
+                        self.frame_buffer[ix] = match color_no {
+                            0 => backdrop, // transparent
+                            _ => self.border_palettes[palette << 4 | color_no],
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    /// the game screen is colored by the attribute of the 8x8 cell
+    pub fn render(&mut self, ppu: &PPU) {
+        self.render_border();
+
+        let index_buffer: &[u8] = ppu.get_index_buffer();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let ix: usize = y * SCREEN_WIDTH + x;
+                let palette: usize =
+                    self.attributes[(y >> 3) * ATTRIBUTE_WIDTH + (x >> 3)] as usize;
+                self.game_screen[ix] = match self.mask {
+                    0x01 => self.game_screen[ix], // freeze
+                    0x02 => 0x0000,               // black
+                    0x03 => self.palettes[0][0],  // color 0
+                    _ => self.palettes[palette][(index_buffer[ix] & 0x03) as usize],
+                };
+            }
+
+            let offset: usize = (y + SCREEN_Y) * SGB_WIDTH + SCREEN_X;
+            self.frame_buffer[offset..offset + SCREEN_WIDTH]
+                .copy_from_slice(&self.game_screen[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]);
+        }
+    }
+
+    /// rgb555 per pixel
+    pub fn get_frame_buffer(&self) -> &[u16] {
+        &self.frame_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAL01: u8 = 0x01;
+    const MASK_EN: u8 = 0x17 << 3 | 0x01;
+
+    fn send(sgb: &mut Sgb, ppu: &PPU, command: u8, data: u8) {
+        let mut packet: [u8; 16] = [0; 16];
+        packet[0] = command;
+        packet[1] = data;
+        sgb.receive(packet, ppu, &mut Joypad::new());
+    }
+
+    /// every bg pixel is shade 3
+    fn render_shade3(ppu: &mut PPU) {
+        for address in 0x8000..0x8010 {
+            ppu.write(address, 0xff);
+        }
+        ppu.write(0xff47, 0xe4);
+        ppu.write(0xff40, 0x91);
+        for _ in 0..70224 {
+            ppu.update(1); // one frame
+        }
+    }
+
+    fn get_game_pixel(sgb: &Sgb, x: usize, y: usize) -> u16 {
+        sgb.get_frame_buffer()[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X]
+    }
+
+    #[test]
+    fn freeze_keeps_the_game_screen() {
+        let mut ppu: PPU = PPU::new(0);
+        let mut sgb: Sgb = Sgb::new(0);
+        sgb.render(&ppu);
+        assert_eq!(get_game_pixel(&sgb, 0, 0), DMG_SHADES[0]);
+
+        send(&mut sgb, &ppu, MASK_EN, 0x01); // freeze
+        render_shade3(&mut ppu);
+        assert_eq!(ppu.get_index_buffer()[0] & 0x03, 0x03);
+        send(&mut sgb, &ppu, PAL01, 0x1f); // the border backdrop is red, shade 3 is black
+        sgb.render(&ppu);
+        assert_eq!(sgb.get_frame_buffer()[0], 0x001f);
+        assert_eq!(get_game_pixel(&sgb, 0, 0), DMG_SHADES[0]);
+        assert_eq!(get_game_pixel(&sgb, 159, 143), DMG_SHADES[0]);
+
+        send(&mut sgb, &ppu, MASK_EN, 0x00); // cancel
+        sgb.render(&ppu);
+        assert_eq!(get_game_pixel(&sgb, 0, 0), 0x0000);
+    }
+}
@@ -0,0 +1,298 @@
+/* Post-processing of rgb555 frames */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    None, // the texture is stretched to the window
+    Nearest,
+    Scale2x,
+    Scale3x,
+    HQ2x,
+    LcdGrid,
+}
+
+fn get_rgb(color: u16) -> (u32, u32, u32) {
+    (
+        (color & 0x1f) as u32,
+        ((color >> 5) & 0x1f) as u32,
+        ((color >> 10) & 0x1f) as u32,
+    )
+}
+
+/// weighted average of the colors
+fn blend(colors: &[(u16, u32)]) -> u16 {
+    let total: u32 = colors.iter().map(|(_, weight)| weight).sum();
+    let (red, green, blue) = colors.iter().fold((0, 0, 0), |sum, (color, weight)| {
+        let (red, green, blue) = get_rgb(*color);
+        (
+            sum.0 + red * weight,
+            sum.1 + green * weight,
+            sum.2 + blue * weight,
+        )
+    });
+    ((blue / total) << 10 | (green / total) << 5 | (red / total)) as u16
+}
+
+/// yuv thresholds, y:48, u:7, v:6
+fn is_different(color1: u16, color2: u16) -> bool {
+    let to_yuv = |color: u16| {
+        let (red, green, blue) = get_rgb(color);
+        let (red, green, blue) = ((red << 3) as i32, (green << 3) as i32, (blue << 3) as i32);
+        (
+            (red + green + blue) >> 2,
+            (red - blue) >> 2,
+            (2 * green - red - blue) >> 3,
+        )
+    };
+    let (y1, u1, v1) = to_yuv(color1);
+    let (y2, u2, v2) = to_yuv(color2);
+    (y1 - y2).abs() > 48 || (u1 - u2).abs() > 7 || (v1 - v2).abs() > 6
+}
+
+/// top left dot of hq2x, the other dots rotate the neighbours to the top left
+#[derive(Clone, Copy)]
+enum Rule {
+    Blend(u8),
+    Diff(u8, u8),         // w4 and w2 differ ? first : second
+    DiffNext(u8, u8),     // w2 and w6
+    DiffPrevious(u8, u8), // w8 and w4
+}
+
+const HQ2X_RULES: [Rule; 14] = [
+    Rule::Blend(10),
+    Rule::Blend(11),
+    Rule::Blend(12),
+    Rule::Blend(20),
+    Rule::Blend(21),
+    Rule::Blend(22),
+    Rule::Diff(0, 20),
+    Rule::Diff(10, 20),
+    Rule::Diff(10, 70),
+    Rule::Diff(0, 90),
+    Rule::Diff(10, 90),
+    Rule::Diff(0, 100),
+    Rule::DiffNext(11, 60),
+    Rule::DiffPrevious(12, 61),
+];
+
+/// rule of the top left dot for each pattern, bit 0-7:w1 w2 w3 w4 w6 w7 w8 w9 differ from w5
+const HQ2X_TABLE: [u8; 256] = [
+    3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 7, 6, 4, 2, 10, 9, 3, 3, 5, 12, 3, 3, 5, 12, 4, 2, 6, 6, 4, 2, 0,
+    6, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 10, 9, 4, 2, 8, 11, 3, 3, 5, 12, 3, 3, 5, 12, 4, 2, 8, 6, 4,
+    2, 0, 11, 3, 3, 5, 1, 3, 3, 5, 1, 4, 13, 6, 6, 4, 13, 8, 6, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 8, 6,
+    4, 2, 8, 6, 3, 3, 5, 1, 3, 3, 5, 1, 4, 13, 0, 6, 4, 13, 0, 11, 3, 3, 5, 1, 3, 3, 5, 12, 4, 2,
+    8, 6, 4, 13, 0, 11, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 7, 6, 4, 2, 10, 9, 3, 3, 5, 1, 3, 3, 5, 1, 4,
+    2, 8, 6, 4, 2, 8, 6, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 10, 9, 4, 2, 8, 11, 3, 3, 5, 1, 3, 3, 5, 1,
+    4, 2, 8, 9, 4, 2, 0, 11, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 8, 6, 4, 2, 8, 9, 3, 3, 5, 1, 3, 3, 5,
+    1, 4, 2, 8, 6, 4, 2, 0, 6, 3, 3, 5, 1, 3, 3, 5, 1, 4, 2, 8, 6, 4, 2, 0, 11, 3, 3, 5, 1, 3, 3,
+    5, 1, 4, 2, 0, 6, 4, 2, 0, 11,
+];
+
+/// 3x3 neighbours w1-w9 turned a quarter counter-clockwise, the top right dot becomes the top left
+fn rotate(neighbours: [u16; 9]) -> [u16; 9] {
+    let [w1, w2, w3, w4, w5, w6, w7, w8, w9] = neighbours;
+    [w3, w6, w9, w2, w5, w8, w1, w4, w7]
+}
+
+/// PIXEL00_<mode> interpolations of hq2x, w5 is blended with w1 (above left), w2 (above) and w4 (left)
+fn interpolate(mode: u8, neighbours: &[u16; 9]) -> u16 {
+    let [w1, w2, _, w4, w5, ..] = *neighbours;
+    match mode {
+        10 => blend(&[(w5, 3), (w1, 1)]),
+        11 => blend(&[(w5, 3), (w4, 1)]),
+        12 => blend(&[(w5, 3), (w2, 1)]),
+        20 => blend(&[(w5, 2), (w4, 1), (w2, 1)]),
+        21 => blend(&[(w5, 2), (w1, 1), (w2, 1)]),
+        22 => blend(&[(w5, 2), (w1, 1), (w4, 1)]),
+        60 => blend(&[(w5, 5), (w2, 2), (w4, 1)]),
+        61 => blend(&[(w5, 5), (w4, 2), (w2, 1)]),
+        70 => blend(&[(w5, 6), (w4, 1), (w2, 1)]),
+        90 => blend(&[(w5, 2), (w4, 3), (w2, 3)]),
+        100 => blend(&[(w5, 14), (w4, 1), (w2, 1)]),
+        _ => w5,
+    }
+}
+
+fn get_hq2x_dot(neighbours: &[u16; 9]) -> u16 {
+    let [w1, w2, w3, w4, w5, w6, w7, w8, w9] = *neighbours;
+    let pattern: usize = [w1, w2, w3, w4, w6, w7, w8, w9]
+        .iter()
+        .enumerate()
+        .filter(|(_, color)| **color != w5 && is_different(w5, **color))
+        .fold(0, |pattern, (bit, _)| pattern | 1 << bit);
+    let mode: u8 = match HQ2X_RULES[HQ2X_TABLE[pattern] as usize] {
+        Rule::Blend(mode) => mode,
+        Rule::Diff(first, second) => match is_different(w4, w2) {
+            true => first,
+            _ => second,
+        },
+        Rule::DiffNext(first, second) => match is_different(w2, w6) {
+            true => first,
+            _ => second,
+        },
+        Rule::DiffPrevious(first, second) => match is_different(w8, w4) {
+            true => first,
+            _ => second,
+        },
+    };
+    interpolate(mode, neighbours)
+}
+
+impl Filter {
+    /// scale2x, scale3x and hq2x have a fixed scale
+    pub fn get_scale(&self, scale: u32) -> u32 {
+        match self {
+            Filter::Scale2x | Filter::HQ2x => 2,
+            Filter::Scale3x => 3,
+            _ => scale,
+        }
+    }
+
+    /// output is (width * scale) x (height * scale)
+    pub fn apply(
+        &self,
+        frame: &[u16],
+        width: usize,
+        height: usize,
+        scale: usize,
+        output: &mut [u16],
+    ) {
+        let output_width: usize = width * scale;
+
+        for y in 0..height {
+            for x in 0..width {
+                // 3x3 neighbours, the edges are repeated
+                let get = |offset_x: isize, offset_y: isize| {
+                    let x: usize = (x as isize + offset_x).clamp(0, width as isize - 1) as usize;
+                    let y: usize = (y as isize + offset_y).clamp(0, height as isize - 1) as usize;
+                    frame[y * width + x]
+                };
+                let (a, b, c) = (get(-1, -1), get(0, -1), get(1, -1));
+                let (d, e, f) = (get(-1, 0), get(0, 0), get(1, 0));
+                let (g, h, i) = (get(-1, 1), get(0, 1), get(1, 1));
+
+                // dot of the scaled pixel, written straight to the output
+                let offset: usize = y * scale * output_width + x * scale;
+                let mut put = |dot_x: usize, dot_y: usize, color: u16| {
+                    output[offset + dot_y * output_width + dot_x] = color;
+                };
+
+                match self {
+                    Filter::Scale2x => {
+                        let is_edge: bool = b != h && d != f;
+                        put(0, 0, if is_edge && d == b { d } else { e });
+                        put(1, 0, if is_edge && b == f { f } else { e });
+                        put(0, 1, if is_edge && d == h { d } else { e });
+                        put(1, 1, if is_edge && h == f { f } else { e });
+                    }
+                    Filter::Scale3x => {
+                        let is_edge: bool = b != h && d != f;
+                        let (top_left, top_right) = (is_edge && d == b, is_edge && b == f);
+                        let (bottom_left, bottom_right) = (is_edge && d == h, is_edge && h == f);
+                        put(0, 0, if top_left { d } else { e });
+                        put(
+                            1,
+                            0,
+                            if (top_left && e != c) || (top_right && e != a) {
+                                b
+                            } else {
+                                e
+                            },
+                        );
+                        put(2, 0, if top_right { f } else { e });
+                        put(
+                            0,
+                            1,
+                            if (top_left && e != g) || (bottom_left && e != a) {
+                                d
+                            } else {
+                                e
+                            },
+                        );
+                        put(1, 1, e);
+                        put(
+                            2,
+                            1,
+                            if (top_right && e != i) || (bottom_right && e != c) {
+                                f
+                            } else {
+                                e
+                            },
+                        );
+                        put(0, 2, if bottom_left { d } else { e });
+                        put(
+                            1,
+                            2,
+                            if (bottom_left && e != i) || (bottom_right && e != g) {
+                                h
+                            } else {
+                                e
+                            },
+                        );
+                        put(2, 2, if bottom_right { f } else { e });
+                    }
+                    Filter::HQ2x => {
+                        // top left, top right, bottom right, bottom left
+                        let mut neighbours: [u16; 9] = [a, b, c, d, e, f, g, h, i];
+                        for (dot_x, dot_y) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+                            put(dot_x, dot_y, get_hq2x_dot(&neighbours));
+                            neighbours = rotate(neighbours);
+                        }
+                    }
+                    Filter::LcdGrid => {
+                        // the last row and column of the dot are the gap
+                        let gap: u16 = blend(&[(e, 3), (0x0000, 1)]);
+                        for dot_y in 0..scale {
+                            for dot_x in 0..scale {
+                                let is_gap: bool =
+                                    scale > 1 && (dot_x == scale - 1 || dot_y == scale - 1);
+                                put(dot_x, dot_y, if is_gap { gap } else { e });
+                            }
+                        }
+                    }
+                    _ => {
+                        for dot_y in 0..scale {
+                            for dot_x in 0..scale {
+                                put(dot_x, dot_y, e);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hq2x_follows_the_rotation_of_the_frame() {
+        // a diagonal line and a dot in a 4x4 frame
+        let frame: [u16; 16] = [
+            0x7fff, 0x7fff, 0x7fff, 0x0000, //
+            0x7fff, 0x7fff, 0x0000, 0x7fff, //
+            0x7fff, 0x0000, 0x7fff, 0x03e0, //
+            0x0000, 0x7fff, 0x7fff, 0x7fff, //
+        ];
+        // a quarter counter-clockwise, x becomes y and y becomes 3 - x
+        let mut rotated: [u16; 16] = [0; 16];
+        for y in 0..4 {
+            for x in 0..4 {
+                rotated[(3 - x) * 4 + y] = frame[y * 4 + x];
+            }
+        }
+        let mut output: [u16; 64] = [0; 64];
+        let mut rotated_output: [u16; 64] = [0; 64];
+        Filter::HQ2x.apply(&frame, 4, 4, 2, &mut output);
+        Filter::HQ2x.apply(&rotated, 4, 4, 2, &mut rotated_output);
+        for y in 0..8 {
+            for x in 0..8 {
+                assert_eq!(rotated_output[(7 - x) * 8 + y], output[y * 8 + x]);
+            }
+        }
+
+        // flat areas are kept, the outer corner of the line is rounded
+        assert_eq!(output[0], 0x7fff);
+        assert!(![0x0000, 0x7fff].contains(&output[2 * 8 + 4]));
+    }
+}
@@ -1,11 +1,14 @@
 extern crate sdl2;
 use sdl2::audio::AudioQueue;
+use sdl2::controller::Axis;
 use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
 
 use std::time;
 
 mod dmg01cpu;
+mod filter;
 
 pub enum LogMode {
     INFO = 0x01,
@@ -67,6 +70,11 @@ impl Common {
     pub const FPS: u8 = 60;
     pub const RAM_FILE_EXTENSION: &'static str = ".sav";
     pub const RTC_FILE_EXTENSION: &'static str = ".rtc";
+    pub const CAMERA_FILE_EXTENSION: &'static str = ".pgm";
+    pub const TMP_FILE_EXTENSION: &'static str = ".tmp";
+    pub const BACKUP_FILE_EXTENSION: &'static str = ".bak";
+    pub const CPU_CLOCK: u32 = 4194304; // Hz
+    pub const AUTOSAVE_INTERVAL: u32 = 10; // seconds of emulated time
 
     /*
     fn is_bit_n_on(value: u8, bit: u8) -> bool {
@@ -94,18 +102,37 @@ fn main() {
     let mut log_mode = 0;
     let romfile: String; // rom file path
     let mut system: dmg01cpu::Dmg01Cpu;
+    let mut pixel_format: PixelFormatEnum = PixelFormatEnum::RGBA32;
+    let mut filter: filter::Filter = filter::Filter::None;
+    let mut scale: u32 = 2; // window size
 
     println!("A Game Boy emulator in Rust.");
 
     let args: std::env::Args = std::env::args();
-    if 1 < args.len() && args.len() < 4 {
+    if 1 < args.len() {
         romfile = std::env::args().nth(1).unwrap();
         println!("ROM:{}", romfile);
 
-        if args.len() == 3 {
-            let value: String = std::env::args().nth(2).unwrap();
-            if value.len() == 2 {
-                match value.parse::<u8>() {
+        let mut renderer = dmg01cpu::Renderer::Scanline;
+        let mut palette: String = String::from("classic");
+        for value in std::env::args().skip(2) {
+            match value.split_once('=') {
+                Some(("--renderer", "scanline")) => renderer = dmg01cpu::Renderer::Scanline,
+                Some(("--renderer", "fifo")) => renderer = dmg01cpu::Renderer::Fifo,
+                Some(("--palette", name)) => palette = String::from(name),
+                Some(("--texture", "rgba8888")) => pixel_format = PixelFormatEnum::RGBA32,
+                Some(("--texture", "rgb565")) => pixel_format = PixelFormatEnum::RGB565,
+                Some(("--filter", "none")) => filter = filter::Filter::None,
+                Some(("--filter", "nearest")) => filter = filter::Filter::Nearest,
+                Some(("--filter", "scale2x")) => filter = filter::Filter::Scale2x,
+                Some(("--filter", "scale3x")) => filter = filter::Filter::Scale3x,
+                Some(("--filter", "hq2x")) => filter = filter::Filter::HQ2x,
+                Some(("--filter", "lcd")) => filter = filter::Filter::LcdGrid,
+                Some(("--scale", number)) => match number.parse::<u32>() {
+                    Ok(result) if (1..=8).contains(&result) => scale = result,
+                    _ => println!("unknown option:{}", value),
+                },
+                _ if value.len() == 2 => match value.parse::<u8>() {
                     Ok(_) => {
                         let mode0: u8 = value.chars().nth(1).unwrap().to_string().parse().unwrap();
                         let mode1: u8 = value.chars().nth(0).unwrap().to_string().parse().unwrap();
@@ -113,13 +140,16 @@ fn main() {
                         println!("DEBUG MODE {:#04x}", log_mode);
                     }
                     Err(_) => (),
-                };
+                },
+                _ => println!("unknown option:{}", value),
             }
         }
 
         system = dmg01cpu::Dmg01Cpu::new(log_mode, romfile);
+        system.ppu.set_renderer(renderer);
+        system.set_palette(&palette);
     } else {
-        println!("Usage:simple-rustboy <ROM file path>");
+        println!("Usage:simple-rustboy <ROM file path> [debug mode] [--renderer=scanline|fifo] [--palette=classic|gray|green|cgb|file] [--texture=rgba8888|rgb565] [--filter=none|nearest|scale2x|scale3x|hq2x|lcd] [--scale=1-8]");
         std::process::exit(1);
     }
 
@@ -131,8 +161,16 @@ fn main() {
         Ok(result) => result,
         Err(error) => panic!("sdl2 video error:{}", error),
     };
+    let (width, height) = system.get_screen_size();
+    let scale: u32 = filter.get_scale(scale);
+    // none:the texture is stretched, others:the texture is scaled by the filter
+    let (texture_width, texture_height) = match filter {
+        filter::Filter::None => (width, height),
+        _ => (width * scale, height * scale),
+    };
+    let mut filtered: Vec<u16> = vec![0; (texture_width * texture_height) as usize];
     let window: sdl2::video::Window = match video
-        .window("simple-rustboy", 320, 288)
+        .window("simple-rustboy", width * scale, height * scale)
         .position_centered()
         .build()
     {
@@ -146,7 +184,8 @@ fn main() {
     let texture_creator: sdl2::render::TextureCreator<sdl2::video::WindowContext> =
         canvas.texture_creator();
     let mut texture =
-        match texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, 160, 144) {
+        match texture_creator.create_texture_streaming(pixel_format, texture_width, texture_height)
+        {
             Ok(result) => result,
             Err(error) => panic!("sdl2 create_texture_streaming error:{}", error),
         };
@@ -156,6 +195,31 @@ fn main() {
         Err(error) => panic!("sdl2 event_pump error:{}", error),
     };
 
+    let game_controller: sdl2::GameControllerSubsystem = match sdl.game_controller() {
+        Ok(result) => result,
+        Err(error) => panic!("sdl2 game_controller error:{}", error),
+    };
+    // first connected game controller for rumble and tilt
+    let mut controller: Option<sdl2::controller::GameController> = None;
+    let num_joysticks: u32 = game_controller.num_joysticks().unwrap_or(0);
+    for index in 0..num_joysticks {
+        if game_controller.is_game_controller(index) {
+            match game_controller.open(index) {
+                Ok(result) => {
+                    println!("Controller:{}", result.name());
+                    controller = Some(result);
+                    break;
+                }
+                Err(error) => println!("sdl2 controller open error:{}", error),
+            }
+        }
+    }
+    let mut rumble_strength: u16 = 0x0000;
+    // mbc7 accelerometer:arrow keys or left analog stick
+    let mut tilt_key: (f32, f32) = (0.0, 0.0);
+    let mut tilt_stick: (f32, f32) = (0.0, 0.0);
+    const STICK_DEAD_ZONE: i16 = 4000;
+
     let audio: sdl2::AudioSubsystem = match sdl.audio() {
         Ok(result) => result,
         Err(error) => panic!("sdl2 video error:{}", error),
@@ -183,7 +247,7 @@ fn main() {
         let mut cycle: u32 = 0;
 
         // cpu clock 4.194304 MHz
-        while cycle < 4194304 / Common::FPS as u32 {
+        while cycle < Common::CPU_CLOCK / Common::FPS as u32 {
             cycle += system.execute() as u32;
         }
 
@@ -202,52 +266,103 @@ fn main() {
         }
         audio_queue.queue_audio(&wave).unwrap();
 
-        texture
-            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                let frame_buffer = system.ppu.get_frame_buffer();
-
-                for y in 0..144 {
-                    for x in 0..160 {
-                        let offset = y * pitch + x * 3;
-                        let color = frame_buffer[y * 160 + x];
-
-                        buffer[offset] = color.saturating_sub(25);
-                        buffer[offset + 1] = color;
-                        buffer[offset + 2] = color.saturating_sub(25);
+        if filter == filter::Filter::None {
+            texture
+                .with_lock(None, |buffer: &mut [u8], pitch: usize| match pixel_format {
+                    PixelFormatEnum::RGB565 => system.write_rgb565(buffer, pitch),
+                    _ => system.write_rgba8888(buffer, pitch),
+                })
+                .unwrap();
+        } else {
+            filter.apply(
+                system.get_frame_buffer(),
+                width as usize,
+                height as usize,
+                scale as usize,
+                &mut filtered,
+            );
+            let filtered_width: usize = texture_width as usize;
+            texture
+                .with_lock(None, |buffer: &mut [u8], pitch: usize| match pixel_format {
+                    PixelFormatEnum::RGB565 => {
+                        dmg01cpu::write_rgb565(&filtered, filtered_width, buffer, pitch)
                     }
-                }
-            })
-            .unwrap();
+                    _ => dmg01cpu::write_rgba8888(&filtered, filtered_width, buffer, pitch),
+                })
+                .unwrap();
+        }
 
         //canvas.set_draw_color(sdl2::pixels::Color::RGB(175, 200, 175));
         canvas.clear();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
 
+        // the duty cycle of the frame scales the strength
+        let strength: u16 = (system.cartridge.get_rumble() * 65535.0) as u16;
+        if let Some(controller) = controller.as_mut() {
+            if rumble_strength != strength {
+                rumble_strength = strength;
+                // keep the motor running until the next change
+                if let Err(error) = controller.set_rumble(strength, strength, u32::MAX) {
+                    Log::info(format!("sdl2 set_rumble error:{}", error), log_mode);
+                }
+            }
+        }
+
         for event in events.poll_iter() {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
-                    keycode: Some(sdl2::keyboard::Keycode::Escape),
+                    keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
                 Event::KeyDown {
                     keycode: Some(keycode),
                     ..
-                } => system.joypad.keydown(keycode),
+                } => {
+                    system.joypad.keydown(keycode);
+                    match keycode {
+                        Keycode::Left => tilt_key.0 = -1.0,
+                        Keycode::Right => tilt_key.0 = 1.0,
+                        Keycode::Up => tilt_key.1 = -1.0,
+                        Keycode::Down => tilt_key.1 = 1.0,
+                        _ => (),
+                    }
+                }
                 Event::KeyUp {
                     keycode: Some(keycode),
                     ..
-                } => system.joypad.keyup(keycode),
+                } => {
+                    system.joypad.keyup(keycode);
+                    match keycode {
+                        Keycode::Left | Keycode::Right => tilt_key.0 = 0.0,
+                        Keycode::Up | Keycode::Down => tilt_key.1 = 0.0,
+                        _ => (),
+                    }
+                }
+                Event::ControllerAxisMotion { axis, value, .. } => {
+                    let value: f32 = if value.unsigned_abs() < STICK_DEAD_ZONE as u16 {
+                        0.0
+                    } else {
+                        value as f32 / i16::MAX as f32
+                    };
+                    match axis {
+                        Axis::LeftX => tilt_stick.0 = value,
+                        Axis::LeftY => tilt_stick.1 = value,
+                        _ => (),
+                    }
+                }
                 _ => (),
             }
         }
+        system
+            .cartridge
+            .set_tilt(tilt_key.0 + tilt_stick.0, tilt_key.1 + tilt_stick.1);
 
         let elapsed: time::Duration = start.elapsed();
         if elapsed < wait {
             std::thread::sleep(wait - elapsed);
         }
-        system.cartridge.execute();
     }
     system.cartridge.save();
 